@@ -0,0 +1,52 @@
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use kekeke::{
+    Transaction,
+    input::{self, CsvDialect},
+};
+
+const ROWS: usize = 100_000;
+
+// A little of everything, the way a real file looks like
+fn generate() -> &'static [u8] {
+    let mut input = String::from("type,client,tx,amount\n");
+    for tid in 0..ROWS {
+        let client = tid % 1000;
+        let row = match tid % 10 {
+            0..=5 => format!("deposit,{client},{tid},{}.{:04}\n", tid % 500, tid % 10000),
+            6..=8 => format!("withdrawal, {client}, {tid}, 1.5\n"),
+            _ => format!("dispute,{client},{},\n", tid - 9),
+        };
+        input.push_str(&row);
+    }
+    Box::leak(input.into_bytes().into_boxed_slice())
+}
+
+fn ingest(c: &mut Criterion) {
+    let input = generate();
+    let mut group = c.benchmark_group("ingest");
+    group.throughput(Throughput::Elements(ROWS as u64));
+
+    // How every row used to be read, through serde
+    group.bench_function("serde", |b| {
+        b.iter(|| {
+            csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(input)
+                .into_deserialize::<Transaction>()
+                .filter(Result::is_ok)
+                .count()
+        })
+    });
+    group.bench_function("fast", |b| {
+        b.iter(|| {
+            input::read_csv(input, &CsvDialect::default())
+                .unwrap()
+                .filter(|(_, result)| result.is_ok())
+                .count()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, ingest);
+criterion_main!(benches);
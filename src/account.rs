@@ -1,5 +1,11 @@
+#[cfg(feature = "u64-client-ids")]
+use std::collections::HashMap;
+use std::ops::{Index, IndexMut};
+
 use rust_decimal::Decimal;
 
+use crate::{TransactionId, id::ClientId};
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct Account {
     pub total: Decimal,
@@ -10,7 +16,132 @@ pub struct Account {
 }
 
 impl Account {
+    // Can't overflow, the ledger makes sure total = available + held fits to begin with
     pub fn get_available(&self) -> Decimal {
         self.total - self.held
     }
 }
+
+// Running counters of what a client has been up to, the fraud rules judge by those
+// and the extended output prints them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ClientStats {
+    pub deposits: u64,
+    pub withdrawals: u64,
+    // Lifetime sums, saturating rather than overflowing as they are informative only
+    pub deposited: Decimal,
+    pub withdrawn: Decimal,
+    // Disputes that are neither resolved nor charged back yet, and the amount they are about
+    pub open_disputes: u64,
+    pub disputed: Decimal,
+    pub chargebacks: u64,
+    pub rejected: u64,
+    // Most recent transaction of the client, whether it went through or not
+    pub last_tid: Option<TransactionId>,
+}
+
+// Something kept for every client, accounts or stats. With u16 ids there is a slot for every
+// client there could be, which is as fast as it gets. Wider ids only have the clients seen so
+// far, every other one reads as the default
+#[cfg(not(feature = "u64-client-ids"))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clients<T> {
+    slots: Vec<T>,
+}
+
+#[cfg(not(feature = "u64-client-ids"))]
+impl<T: Default + Clone> Default for Clients<T> {
+    fn default() -> Self {
+        Clients {
+            slots: vec![T::default(); ClientId::MAX as usize + 1],
+        }
+    }
+}
+
+#[cfg(not(feature = "u64-client-ids"))]
+impl<T> Clients<T> {
+    // Every client, in client order
+    pub fn iter(&self) -> impl Iterator<Item = (ClientId, &T)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .map(|(cid, value)| (cid as ClientId, value))
+    }
+}
+
+#[cfg(not(feature = "u64-client-ids"))]
+impl<T> Index<ClientId> for Clients<T> {
+    type Output = T;
+
+    fn index(&self, cid: ClientId) -> &T {
+        // SAFETY: there is a slot for every possible client id from the start
+        unsafe { self.slots.get_unchecked(cid as usize) }
+    }
+}
+
+#[cfg(not(feature = "u64-client-ids"))]
+impl<T> IndexMut<ClientId> for Clients<T> {
+    fn index_mut(&mut self, cid: ClientId) -> &mut T {
+        // SAFETY: there is a slot for every possible client id from the start
+        unsafe { self.slots.get_unchecked_mut(cid as usize) }
+    }
+}
+
+#[cfg(feature = "u64-client-ids")]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Clients<T> {
+    clients: HashMap<ClientId, T>,
+    // What the clients not seen yet read as
+    empty: T,
+}
+
+#[cfg(feature = "u64-client-ids")]
+impl<T> Clients<T> {
+    // Every client seen so far, in client order
+    pub fn iter(&self) -> impl Iterator<Item = (ClientId, &T)> {
+        let mut clients: Vec<_> = self
+            .clients
+            .iter()
+            .map(|(cid, value)| (*cid, value))
+            .collect();
+        clients.sort_unstable_by_key(|(cid, _)| *cid);
+        clients.into_iter()
+    }
+}
+
+#[cfg(feature = "u64-client-ids")]
+impl<T> Index<ClientId> for Clients<T> {
+    type Output = T;
+
+    fn index(&self, cid: ClientId) -> &T {
+        self.clients.get(&cid).unwrap_or(&self.empty)
+    }
+}
+
+#[cfg(feature = "u64-client-ids")]
+impl<T: Default> IndexMut<ClientId> for Clients<T> {
+    fn index_mut(&mut self, cid: ClientId) -> &mut T {
+        self.clients.entry(cid).or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clients() {
+        let mut clients = Clients::<u64>::default();
+        clients[7] += 2;
+        clients[3] += 1;
+        assert_eq!(clients[7], 2);
+        assert_eq!(clients[5], 0);
+        assert_eq!(
+            clients
+                .iter()
+                .filter(|(_, value)| **value > 0)
+                .collect::<Vec<_>>(),
+            [(3, &1), (7, &2)]
+        );
+    }
+}
@@ -0,0 +1,322 @@
+use std::{collections::HashMap, fmt};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    Action, ActionKind, ClientId, Transaction, TransactionId, output::Columns, payments::Payments,
+    transaction::ActionStatus,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Invariant {
+    // Held funds must match the disputed transactions and pending authorizations of the client
+    Held,
+    // Total funds must match the net of the applied transactions of the client
+    Total,
+    // Once a transaction is final or reverted, it must not hold any funds anymore
+    FinalHoldsFunds,
+}
+
+impl fmt::Display for Invariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Invariant::Held => "held",
+            Invariant::Total => "total",
+            Invariant::FinalHoldsFunds => "final-holds-funds",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Violation {
+    // The transaction after which the invariant no longer held, if known
+    pub tid: Option<TransactionId>,
+    #[serde(rename = "client")]
+    pub cid: ClientId,
+    pub invariant: Invariant,
+    pub expected: Decimal,
+    pub actual: Decimal,
+}
+
+impl Columns for Violation {
+    const COLUMNS: &'static [&'static str] = &["tid", "client", "invariant", "expected", "actual"];
+}
+
+// What the action should contribute to the (total, held) balances of its client,
+// judging solely by its kind and status
+fn expected_balances(action: &Action) -> (Decimal, Decimal) {
+    let zero = Decimal::ZERO;
+    match action.kind {
+        ActionKind::Deposit { amount } => {
+            let net = amount - action.refunded;
+            match action.status {
+                ActionStatus::Fresh | ActionStatus::Final => (net, zero),
+                ActionStatus::Disputed => (net, net),
+                ActionStatus::Reverted => (zero, zero),
+            }
+        }
+        // A disputed withdrawal is back in the total, but frozen
+        ActionKind::Withdrawal { amount } => {
+            let net = amount - action.refunded;
+            match action.status {
+                ActionStatus::Fresh | ActionStatus::Final => (-net, zero),
+                ActionStatus::Disputed => (zero, net),
+                ActionStatus::Reverted => (zero, zero),
+            }
+        }
+        ActionKind::Authorization { amount } => match action.status {
+            ActionStatus::Fresh => (zero, amount),
+            _ => (zero, zero),
+        },
+    }
+}
+
+fn sum_balances<'a>(actions: impl Iterator<Item = &'a Action>) -> (Decimal, Decimal) {
+    actions.map(expected_balances).fold(
+        (Decimal::ZERO, Decimal::ZERO),
+        |(total, held), (action_total, action_held)| (total + action_total, held + action_held),
+    )
+}
+
+// Whether the action is settled, but what it held when disputed is still held, which would
+// explain the client holding that much too much
+fn still_holds(action: &Action, excess: Decimal) -> bool {
+    let disputed = Action {
+        status: ActionStatus::Disputed,
+        ..action.clone()
+    };
+    matches!(action.status, ActionStatus::Final | ActionStatus::Reverted)
+        && expected_balances(&disputed).1 == excess
+}
+
+fn compare(
+    payments: &Payments,
+    cid: ClientId,
+    tid: Option<TransactionId>,
+    (total, held): (Decimal, Decimal),
+) -> Vec<Violation> {
+    let account = &payments.accounts[cid];
+    let mut violations = Vec::new();
+    if account.held != held {
+        // Blaming the referenced transaction, should it have just become final
+        let has_just_settled =
+            tid.and_then(|tid| payments.actions.get(&tid))
+                .is_some_and(|action| {
+                    action.cid == cid
+                        && matches!(action.status, ActionStatus::Final | ActionStatus::Reverted)
+                });
+        violations.push(Violation {
+            tid,
+            cid,
+            invariant: if has_just_settled && account.held > held {
+                Invariant::FinalHoldsFunds
+            } else {
+                Invariant::Held
+            },
+            expected: held,
+            actual: account.held,
+        });
+    }
+    if account.total != total {
+        violations.push(Violation {
+            tid,
+            cid,
+            invariant: Invariant::Total,
+            expected: total,
+            actual: account.total,
+        });
+    }
+    violations
+}
+
+// Checks the invariants after every single transaction, so that a violation could be pinned
+// on the transaction that caused it. Only the clients the transaction touched get checked,
+// against the (total, held) balances their actions add up to. Those are kept up to date as
+// the actions change, a transaction only ever changes the action under its own tid
+#[derive(Debug, Default)]
+pub struct Auditor {
+    expected: HashMap<ClientId, (Decimal, Decimal)>,
+}
+
+impl Auditor {
+    // Should be called right after the transaction went through the engine, along with the
+    // action under the same tid as it was before, if there was any
+    pub fn check(
+        &mut self,
+        payments: &Payments,
+        transaction: &Transaction,
+        previous: Option<&Action>,
+    ) -> Vec<Violation> {
+        if let Some(previous) = previous {
+            let (total, held) = expected_balances(previous);
+            let expected = self.expected.entry(previous.cid).or_default();
+            *expected = (expected.0 - total, expected.1 - held);
+        }
+        if let Some(action) = payments.actions.get(&transaction.tid) {
+            let (total, held) = expected_balances(action);
+            let expected = self.expected.entry(action.cid).or_default();
+            *expected = (expected.0 + total, expected.1 + held);
+        }
+
+        let mut clients = vec![transaction.cid];
+        clients.extend(
+            previous
+                .map(|previous| previous.cid)
+                .filter(|cid| *cid != transaction.cid),
+        );
+        clients
+            .into_iter()
+            .flat_map(|cid| {
+                let expected = self.expected.get(&cid).copied().unwrap_or_default();
+                compare(payments, cid, Some(transaction.tid), expected)
+            })
+            .collect()
+    }
+}
+
+// Checks the invariants of every active account in one go, at the end of a run. With no
+// transaction to blame, a client holding too much is pinned on a settled action of theirs
+// which still holds funds, should there be one
+pub fn audit(payments: &Payments) -> Vec<Violation> {
+    let mut expected: HashMap<ClientId, Vec<(TransactionId, &Action)>> = HashMap::new();
+    for (tid, action) in &payments.actions {
+        expected.entry(action.cid).or_default().push((*tid, action));
+    }
+
+    payments
+        .accounts
+        .iter()
+        .filter(|(_, account)| account.has_activity)
+        .flat_map(|(cid, account)| {
+            let actions = expected.get(&cid).map(Vec::as_slice).unwrap_or_default();
+            let balances = sum_balances(actions.iter().map(|(_, action)| *action));
+            let excess = account.held - balances.1;
+            let settled = actions
+                .iter()
+                .filter(|(_, action)| excess > Decimal::ZERO && still_holds(action, excess))
+                .map(|(tid, _)| *tid)
+                .min();
+            compare(payments, cid, settled, balances)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TransactionKind, id::tid};
+    use rust_decimal_macros::dec;
+
+    fn transaction(cid: ClientId, tid: TransactionId, kind: TransactionKind) -> Transaction {
+        Transaction {
+            cid,
+            tid,
+            timestamp: None,
+            kind,
+        }
+    }
+
+    #[test]
+    fn consistent_run_has_no_violations() {
+        let mut payments = Payments {
+            auditor: Some(Auditor::default()),
+            ..Default::default()
+        };
+        let transactions = [
+            transaction(0, tid(0), TransactionKind::Deposit { amount: dec!(10) }),
+            transaction(0, tid(1), TransactionKind::Withdrawal { amount: dec!(4) }),
+            transaction(0, tid(1), TransactionKind::Dispute),
+            transaction(0, tid(0), TransactionKind::Refund { amount: None }),
+            transaction(
+                0,
+                tid(0),
+                TransactionKind::Refund {
+                    amount: Some(dec!(3)),
+                },
+            ),
+            transaction(0, tid(2), TransactionKind::Authorize { amount: dec!(2) }),
+            transaction(0, tid(1), TransactionKind::Resolve),
+            transaction(1, tid(3), TransactionKind::Deposit { amount: dec!(5) }),
+            transaction(1, tid(3), TransactionKind::Dispute),
+            transaction(1, tid(3), TransactionKind::Chargeback),
+        ];
+
+        for transaction in &transactions {
+            let _ = payments.process_transaction(transaction);
+        }
+
+        assert_eq!(payments.violations, vec![]);
+        assert_eq!(audit(&payments), vec![]);
+    }
+
+    // Reusing a tid silently drops the earlier action, the audit should notice
+    #[test]
+    fn duplicate_tid_is_blamed() {
+        let mut payments = Payments {
+            auditor: Some(Auditor::default()),
+            ..Default::default()
+        };
+        let _ = payments.process_transaction(&transaction(
+            0,
+            tid(7),
+            TransactionKind::Deposit { amount: dec!(10) },
+        ));
+        let _ = payments.process_transaction(&transaction(
+            1,
+            tid(7),
+            TransactionKind::Deposit { amount: dec!(5) },
+        ));
+
+        assert_eq!(
+            payments.violations,
+            vec![Violation {
+                tid: Some(tid(7)),
+                cid: 0,
+                invariant: Invariant::Total,
+                expected: dec!(0),
+                actual: dec!(10),
+            }]
+        );
+    }
+
+    #[test]
+    fn final_action_holding_funds() {
+        let mut payments = Payments::default();
+        let _ = payments.process_transaction(&transaction(
+            0,
+            tid(0),
+            TransactionKind::Deposit { amount: dec!(10) },
+        ));
+        let _ = payments.process_transaction(&transaction(0, tid(0), TransactionKind::Dispute));
+        // Settling the action behind the engine's back, the funds stay held
+        payments.actions.get_mut(&tid(0)).unwrap().status = ActionStatus::Final;
+
+        let expected = sum_balances(payments.actions.values());
+        assert_eq!(
+            compare(&payments, 0, Some(tid(0)), expected),
+            vec![Violation {
+                tid: Some(tid(0)),
+                cid: 0,
+                invariant: Invariant::FinalHoldsFunds,
+                expected: dec!(0),
+                actual: dec!(10),
+            }]
+        );
+        assert_eq!(
+            audit(&payments),
+            vec![Violation {
+                tid: Some(tid(0)),
+                cid: 0,
+                invariant: Invariant::FinalHoldsFunds,
+                expected: dec!(0),
+                actual: dec!(10),
+            }]
+        );
+
+        // Held funds no settled action explains are just that
+        payments.accounts[0].held += dec!(1);
+        assert_eq!(audit(&payments)[0].invariant, Invariant::Held);
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt;
+
+// Rows a run has to read before the error rate is worth checking, a few faulty rows at the
+// start of the input would be too high a rate otherwise
+pub const MIN_RATE_ROWS: u64 = 1000;
+
+// How many faulty rows, either unparsable or rejected, a run puts up with before it gives up.
+// The spec wants us to carry on no matter what, which is what the default does
+#[derive(Debug, Default, Clone)]
+pub struct ErrorBudget {
+    pub max_errors: Option<u64>,
+    // Faulty rows per row, checked as the rows come in once there are `min_rate_rows` of them,
+    // and once the whole input is read
+    pub max_error_rate: Option<f64>,
+    pub min_rate_rows: u64,
+    rows: u64,
+    errors: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BudgetExceeded {
+    TooManyErrors { max_errors: u64 },
+    ErrorRateTooHigh { rate: f64, max_error_rate: f64 },
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BudgetExceeded::TooManyErrors { max_errors: 0 } => f.write_str("no errors are allowed"),
+            BudgetExceeded::TooManyErrors { max_errors } => {
+                write!(f, "more than {max_errors} errors")
+            }
+            BudgetExceeded::ErrorRateTooHigh {
+                rate,
+                max_error_rate,
+            } => write!(f, "error rate {rate} is above {max_error_rate}"),
+        }
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+impl ErrorBudget {
+    pub fn new(max_errors: Option<u64>, max_error_rate: Option<f64>) -> Self {
+        ErrorBudget {
+            max_errors,
+            max_error_rate,
+            min_rate_rows: MIN_RATE_ROWS,
+            ..Default::default()
+        }
+    }
+
+    pub fn record(&mut self, faulty: bool) -> Result<(), BudgetExceeded> {
+        self.rows += 1;
+        if faulty {
+            self.errors += 1;
+        }
+        match self.max_errors {
+            Some(max_errors) if self.errors > max_errors => {
+                Err(BudgetExceeded::TooManyErrors { max_errors })
+            }
+            _ if self.rows >= self.min_rate_rows => self.check_rate(),
+            _ => Ok(()),
+        }
+    }
+
+    // For rows recorded as fine, which turned out to be faulty later on
+    pub fn record_errors(&mut self, errors: u64) {
+        self.errors += errors;
+    }
+
+    // Has to go once the input is over, an empty input has no rate at all
+    pub fn finish(&self) -> Result<(), BudgetExceeded> {
+        self.check_rate()
+    }
+
+    fn check_rate(&self) -> Result<(), BudgetExceeded> {
+        match self.max_error_rate {
+            Some(max_error_rate) if self.rows > 0 => {
+                let rate = self.errors as f64 / self.rows as f64;
+                if rate > max_error_rate {
+                    return Err(BudgetExceeded::ErrorRateTooHigh {
+                        rate,
+                        max_error_rate,
+                    });
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// For clap, a rate is a fraction of the rows
+pub fn parse_rate(value: &str) -> Result<f64, String> {
+    let rate: f64 = value.parse().map_err(|error| format!("{error}"))?;
+    if !(0.0..=1.0).contains(&rate) {
+        return Err("the rate has to be between 0 and 1".to_string());
+    }
+    Ok(rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_errors() {
+        let mut budget = ErrorBudget::new(Some(1), None);
+        assert_eq!(budget.record(false), Ok(()));
+        assert_eq!(budget.record(true), Ok(()));
+        assert_eq!(budget.record(false), Ok(()));
+        assert_eq!(
+            budget.record(true),
+            Err(BudgetExceeded::TooManyErrors { max_errors: 1 })
+        );
+
+        let mut strict = ErrorBudget::new(Some(0), None);
+        assert_eq!(
+            strict.record(true).unwrap_err().to_string(),
+            "no errors are allowed"
+        );
+    }
+
+    #[test]
+    fn max_error_rate() {
+        let mut budget = ErrorBudget::new(None, Some(0.25));
+        budget.min_rate_rows = 100;
+        assert_eq!(budget.finish(), Ok(()));
+        for faulty in [true, false, false, false] {
+            budget.record(faulty).unwrap();
+        }
+        assert_eq!(budget.finish(), Ok(()));
+        budget.record(true).unwrap();
+        assert_eq!(
+            budget.finish(),
+            Err(BudgetExceeded::ErrorRateTooHigh {
+                rate: 0.4,
+                max_error_rate: 0.25
+            })
+        );
+
+        // Checked along the way as well, once enough rows were seen
+        let mut budget = ErrorBudget::new(None, Some(0.25));
+        budget.min_rate_rows = 4;
+        for faulty in [true, true, false] {
+            budget.record(faulty).unwrap();
+        }
+        assert_eq!(
+            budget.record(false),
+            Err(BudgetExceeded::ErrorRateTooHigh {
+                rate: 0.5,
+                max_error_rate: 0.25
+            })
+        );
+        let mut budget = ErrorBudget::new(None, Some(0.25));
+        budget.min_rate_rows = 4;
+        for faulty in [true, false, false, false, false] {
+            budget.record(faulty).unwrap();
+        }
+        budget.record_errors(1);
+        assert_eq!(
+            budget.record(false),
+            Err(BudgetExceeded::ErrorRateTooHigh {
+                rate: 2.0 / 6.0,
+                max_error_rate: 0.25
+            })
+        );
+
+        assert!(parse_rate("0.1").is_ok());
+        assert!(parse_rate("1.5").is_err());
+        assert!(parse_rate("many").is_err());
+    }
+}
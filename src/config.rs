@@ -0,0 +1,125 @@
+use serde::Deserialize;
+
+use crate::{input::CsvDialect, output::DecimalFormat, transaction::AmountPolicy};
+
+// Settings that would be a pain to pass on the command line every time. It's a toml file
+// like this, every section and key can be omitted:
+//
+// [amounts]
+// scale = 4
+// rounding = "round"
+// max_amount = "1000000"
+//
+// [output]
+// precision = 2
+// rounding = "half-up"
+// trailing_zeros = "strip"
+//
+// [csv]
+// delimiter = ";"
+// comment = "#"
+//
+// [csv.columns]
+// type = "kind"
+// client = "customer"
+// amount = 4
+//
+// [csv.types]
+// DEP = "deposit"
+//
+// Command line options take precedence over the file
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub amounts: AmountPolicy,
+    pub output: DecimalFormat,
+    pub csv: CsvDialect,
+}
+
+pub fn load_config(path: &str) -> anyhow::Result<Config> {
+    Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        input::{Column, ColumnMapping},
+        output::{Precision, Rounding, TrailingZeros},
+        transaction::ScalePolicy,
+    };
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn parse_full_config() {
+        let config: Config = toml::from_str(
+            r##"
+            [amounts]
+            scale = 4
+            rounding = "truncate"
+            max_amount = "1000.5"
+
+            [output]
+            precision = 2
+            rounding = "half-up"
+            trailing_zeros = "strip"
+
+            [csv]
+            delimiter = ";"
+            has_headers = false
+            comment = "#"
+
+            [csv.columns]
+            type = 1
+            tx = 0
+
+            [csv.types]
+            WD = "withdrawal"
+            "##,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                amounts: AmountPolicy {
+                    scale: Some(4),
+                    scale_policy: ScalePolicy::Truncate,
+                    max_amount: Some(dec!(1000.5)),
+                },
+                output: DecimalFormat {
+                    precision: Precision::Places(2),
+                    rounding: Rounding::HalfUp,
+                    trailing_zeros: TrailingZeros::Strip,
+                },
+                csv: CsvDialect {
+                    delimiter: ';',
+                    has_headers: false,
+                    comment: Some('#'),
+                    columns: ColumnMapping {
+                        kind: Column::Position(1),
+                        tx: Column::Position(0),
+                        ..Default::default()
+                    },
+                    types: [("WD".to_string(), "withdrawal".to_string())].into(),
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn missing_settings_fall_back_to_defaults() {
+        let config: Config = toml::from_str("[output]\nprecision = \"full\"").unwrap();
+
+        assert_eq!(config.amounts, AmountPolicy::default());
+        assert_eq!(config.output.precision, Precision::Full);
+        assert_eq!(config.output.rounding, Rounding::HalfEven);
+    }
+
+    #[test]
+    fn unknown_settings_are_refused() {
+        assert!(toml::from_str::<Config>("[output]\nplaces = 2").is_err());
+        assert!(toml::from_str::<Config>("[output]\nprecision = \"two\"").is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::BTreeSet;
+
+use serde::Serialize;
+
+use crate::{
+    ClientId, TransactionId,
+    output::Columns,
+    state::{SavedAccount, SavedAction, SavedState},
+};
+
+// A single thing that differs between the two sides, empty on the side that lacks it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Difference {
+    pub client: ClientId,
+    // Set for differences in the actions, the client is the one owning the action
+    pub tx: Option<TransactionId>,
+    pub field: &'static str,
+    pub left: String,
+    pub right: String,
+}
+
+impl Columns for Difference {
+    const COLUMNS: &'static [&'static str] = &["client", "tx", "field", "left", "right"];
+}
+
+// Balances are normalized, so that 1.5 and 1.5000 come out the same
+fn account_fields(account: Option<&SavedAccount>) -> [(&'static str, String); 4] {
+    let value = |value: Option<String>| value.unwrap_or_default();
+    [
+        (
+            "available",
+            value(account.map(|account| account.available.normalize().to_string())),
+        ),
+        (
+            "held",
+            value(account.map(|account| account.held.normalize().to_string())),
+        ),
+        (
+            "total",
+            value(account.map(|account| account.total.normalize().to_string())),
+        ),
+        (
+            "locked",
+            value(account.map(|account| account.locked.to_string())),
+        ),
+    ]
+}
+
+fn status(action: Option<&SavedAction>) -> String {
+    action
+        .map(|action| action.status.name().to_string())
+        .unwrap_or_default()
+}
+
+// Every difference in the balances, in client order, followed by every difference in the
+// status of the actions, in tid order. The actions are only compared if both sides have them
+pub fn diff(left: &SavedState, right: &SavedState) -> Vec<Difference> {
+    let mut differences = Vec::new();
+
+    let clients: BTreeSet<_> = left.accounts.keys().chain(right.accounts.keys()).collect();
+    for client in clients {
+        let left_fields = account_fields(left.accounts.get(client));
+        let right_fields = account_fields(right.accounts.get(client));
+        for ((field, left), (_, right)) in left_fields.into_iter().zip(right_fields) {
+            if left != right {
+                differences.push(Difference {
+                    client: *client,
+                    tx: None,
+                    field,
+                    left,
+                    right,
+                });
+            }
+        }
+    }
+
+    if let (Some(left_actions), Some(right_actions)) = (&left.actions, &right.actions) {
+        let tids: BTreeSet<_> = left_actions.keys().chain(right_actions.keys()).collect();
+        for tid in tids {
+            let (left_action, right_action) = (left_actions.get(tid), right_actions.get(tid));
+            let (left, right) = (status(left_action), status(right_action));
+            if left != right {
+                differences.push(Difference {
+                    client: left_action
+                        .or(right_action)
+                        .map_or(0, |action| action.client),
+                    tx: Some(*tid),
+                    field: "status",
+                    left,
+                    right,
+                });
+            }
+        }
+    }
+
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{id::tid, transaction::ActionStatus};
+    use rust_decimal_macros::dec;
+    use std::collections::BTreeMap;
+
+    fn account(total: rust_decimal::Decimal, locked: bool) -> SavedAccount {
+        SavedAccount {
+            available: total,
+            held: dec!(0),
+            total,
+            locked,
+        }
+    }
+
+    fn action(status: ActionStatus) -> SavedAction {
+        SavedAction {
+            client: 1,
+            kind: "deposit".to_string(),
+            amount: dec!(1),
+            refunded: dec!(0),
+            status,
+        }
+    }
+
+    #[test]
+    fn same_balances_differently_printed() {
+        let left = SavedState {
+            accounts: BTreeMap::from([(1, account(dec!(1.5), false))]),
+            actions: None,
+        };
+        let right = SavedState {
+            accounts: BTreeMap::from([(1, account(dec!(1.5000), false))]),
+            actions: Some(BTreeMap::from([(tid(1), action(ActionStatus::Fresh))])),
+        };
+
+        assert_eq!(diff(&left, &right), vec![]);
+    }
+
+    #[test]
+    fn differences_are_reported() {
+        let left = SavedState {
+            accounts: BTreeMap::from([(1, account(dec!(1), false)), (2, account(dec!(2), false))]),
+            actions: Some(BTreeMap::from([
+                (tid(1), action(ActionStatus::Fresh)),
+                (tid(2), action(ActionStatus::Disputed)),
+            ])),
+        };
+        let right = SavedState {
+            accounts: BTreeMap::from([(1, account(dec!(1), true)), (3, account(dec!(3), false))]),
+            actions: Some(BTreeMap::from([
+                (tid(1), action(ActionStatus::Final)),
+                (tid(2), action(ActionStatus::Disputed)),
+                (tid(3), action(ActionStatus::Fresh)),
+            ])),
+        };
+
+        let differences: Vec<_> = diff(&left, &right)
+            .into_iter()
+            .map(|d| (d.client, d.tx, d.field, d.left, d.right))
+            .collect();
+        let string = |s: &str| s.to_string();
+        assert_eq!(
+            differences,
+            vec![
+                (1, None, "locked", string("false"), string("true")),
+                (2, None, "available", string("2"), string("")),
+                (2, None, "held", string("0"), string("")),
+                (2, None, "total", string("2"), string("")),
+                (2, None, "locked", string("false"), string("")),
+                (3, None, "available", string(""), string("3")),
+                (3, None, "held", string(""), string("0")),
+                (3, None, "total", string(""), string("3")),
+                (3, None, "locked", string(""), string("false")),
+                (1, Some(tid(1)), "status", string("fresh"), string("final")),
+                (1, Some(tid(3)), "status", string(""), string("fresh")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::input::parse_integer;
+
+// The spec has transaction ids as u32, partners with bigger or non numeric ids build with
+// the u64-tx-ids or the string-tx-ids feature. Should both be on, strings can take u64s too
+#[cfg(not(any(feature = "u64-tx-ids", feature = "string-tx-ids")))]
+pub type TransactionId = u32;
+#[cfg(all(feature = "u64-tx-ids", not(feature = "string-tx-ids")))]
+pub type TransactionId = u64;
+#[cfg(feature = "string-tx-ids")]
+pub type TransactionId = StringId;
+
+// Client ids are u16 as the spec has them, which lets the engine keep a slot for every
+// client there could be. With the u64-client-ids feature they go into maps instead
+#[cfg(not(feature = "u64-client-ids"))]
+pub type ClientId = u16;
+#[cfg(feature = "u64-client-ids")]
+pub type ClientId = u64;
+
+// Ids as the fast csv path reads them, None leaves the field to the deserializer
+pub trait Id: Sized {
+    fn from_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+impl Id for u16 {
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        parse_integer(bytes)?.try_into().ok()
+    }
+}
+
+impl Id for u32 {
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        parse_integer(bytes)?.try_into().ok()
+    }
+}
+
+impl Id for u64 {
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        parse_integer(bytes)
+    }
+}
+
+// Tests spell transaction ids as numbers, whatever the type. For u32 ids there is nothing to
+// convert
+#[cfg(test)]
+#[allow(clippy::useless_conversion)]
+pub fn tid(id: u32) -> TransactionId {
+    id.into()
+}
+
+// Long enough for a UUID with some room to spare
+const STRING_ID_CAPACITY: usize = 63;
+
+// A non empty string id, kept inline so that it could be Copy
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StringId {
+    len: u8,
+    bytes: [u8; STRING_ID_CAPACITY],
+}
+
+impl StringId {
+    pub fn as_str(&self) -> &str {
+        // Only ever filled from a str, cut at its length
+        std::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or_default()
+    }
+}
+
+impl Default for StringId {
+    fn default() -> Self {
+        StringId {
+            len: 0,
+            bytes: [0; STRING_ID_CAPACITY],
+        }
+    }
+}
+
+impl FromStr for StringId {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.is_empty() {
+            return Err("transaction id must not be empty".to_string());
+        }
+        if value.len() > STRING_ID_CAPACITY {
+            return Err(format!(
+                "transaction id {value} is longer than {STRING_ID_CAPACITY} bytes"
+            ));
+        }
+        let mut id = StringId {
+            len: value.len() as u8,
+            ..Default::default()
+        };
+        id.bytes[..value.len()].copy_from_slice(value.as_bytes());
+        Ok(id)
+    }
+}
+
+// Numbers are taken for their digits, like json ones are
+impl From<u32> for StringId {
+    fn from(id: u32) -> Self {
+        let mut buffer = [0; 10];
+        let mut start = buffer.len();
+        let mut rest = id;
+        loop {
+            start -= 1;
+            buffer[start] = b'0' + (rest % 10) as u8;
+            rest /= 10;
+            if rest == 0 {
+                break;
+            }
+        }
+        let mut id = StringId {
+            len: (buffer.len() - start) as u8,
+            ..Default::default()
+        };
+        id.bytes[..id.len as usize].copy_from_slice(&buffer[start..]);
+        id
+    }
+}
+
+// By the string, not by the length first as the fields would have it
+impl Ord for StringId {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl PartialOrd for StringId {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for StringId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for StringId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl Serialize for StringId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for StringId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Json may have the ids as numbers, they are taken for their digits
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum StringOrNumber<'a> {
+            String(std::borrow::Cow<'a, str>),
+            Number(u64),
+        }
+
+        match StringOrNumber::deserialize(deserializer)? {
+            StringOrNumber::String(value) => value.parse(),
+            StringOrNumber::Number(value) => value.to_string().parse(),
+        }
+        .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Id for StringId {
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        std::str::from_utf8(bytes).ok()?.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn string_ids() {
+        let id: StringId = "6f1c2a9e-53b4-4c1e-9a57-0d2f4b8e7c31".parse().unwrap();
+        assert_eq!(id.to_string(), "6f1c2a9e-53b4-4c1e-9a57-0d2f4b8e7c31");
+        assert_eq!(
+            serde_json::to_string(&id).unwrap(),
+            "\"6f1c2a9e-53b4-4c1e-9a57-0d2f4b8e7c31\""
+        );
+        assert_eq!(
+            serde_json::from_str::<StringId>("\"a\"").unwrap(),
+            "a".parse().unwrap()
+        );
+        assert_eq!(
+            serde_json::from_str::<StringId>("17").unwrap(),
+            "17".parse().unwrap()
+        );
+        assert_eq!(StringId::from_bytes(b"tx-1"), "tx-1".parse().ok());
+        assert_eq!(StringId::from(0), "0".parse().unwrap());
+        assert_eq!(StringId::from(u32::MAX), "4294967295".parse().unwrap());
+
+        assert!("".parse::<StringId>().is_err());
+        assert!("x".repeat(64).parse::<StringId>().is_err());
+        assert!("x".repeat(63).parse::<StringId>().is_ok());
+
+        let mut ids: Vec<StringId> = ["b", "ab", "a"].map(|id| id.parse().unwrap()).to_vec();
+        ids.sort();
+        assert_eq!(
+            ids.iter().map(StringId::as_str).collect::<Vec<_>>(),
+            ["a", "ab", "b"]
+        );
+    }
+
+    #[test]
+    fn numeric_ids() {
+        assert_eq!(u16::from_bytes(b"65536"), None);
+        assert_eq!(u32::from_bytes(b"42"), Some(42));
+        assert_eq!(u32::from_bytes(b"4294967296"), None);
+        assert_eq!(u64::from_bytes(b"4294967296"), Some(4294967296));
+        assert_eq!(u64::from_bytes(b"x"), None);
+        assert_eq!(u64::from_bytes(b"+1"), None);
+    }
+}
@@ -0,0 +1,967 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+    rc::Rc,
+};
+
+use csv::ReaderBuilder;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::{
+    ClientId, Transaction, TransactionId,
+    id::Id,
+    transaction::{CsvTransaction, error_code},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputFormat {
+    Csv,
+    // One json object per line, also known as json lines
+    Ndjson,
+}
+
+impl InputFormat {
+    // Guesses the format by the file extension, anything unknown is taken for csv.
+    // For a compressed file, it's the extension in front of the .gz or .zst that counts
+    pub fn from_path(path: &Path) -> Self {
+        let path = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz" | "zst") => path.file_stem().map_or(path, Path::new),
+            _ => path,
+        };
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ndjson" | "jsonl") => InputFormat::Ndjson,
+            _ => InputFormat::Csv,
+        }
+    }
+}
+
+// Every format comes down to the same stream of transactions, each along with the line of
+// the input it starts on. A faulty record is just an error in the stream, whoever reads it
+// decides whether to go on
+pub type Transactions = Box<dyn Iterator<Item = (u64, anyhow::Result<Transaction>)>>;
+
+// A path of - reads the standard input
+pub fn read_transactions(
+    path: &Path,
+    format: InputFormat,
+    dialect: &CsvDialect,
+) -> anyhow::Result<Transactions> {
+    let reader = if path == Path::new("-") {
+        decompress(io::stdin())?
+    } else {
+        decompress(File::open(path)?)?
+    };
+    match format {
+        InputFormat::Csv => read_csv(reader, dialect),
+        InputFormat::Ndjson => Ok(read_ndjson(reader)),
+    }
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+// Tells a compressed input by its first bytes, so it works for the standard input as well
+// as for files named whatever. Anything else is passed through as it is
+pub fn decompress(mut reader: impl Read + 'static) -> io::Result<Box<dyn BufRead>> {
+    // A pipe may hand out fewer bytes than the magic has in one go, so it's read until
+    // there are enough of them or the input is over
+    let mut magic = [0; ZSTD_MAGIC.len()];
+    let mut read = 0;
+    while read < magic.len() {
+        match reader.read(&mut magic[read..]) {
+            Ok(0) => break,
+            Ok(count) => read += count,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    let magic = &magic[..read];
+    let reader = BufReader::new(io::Cursor::new(magic.to_vec()).chain(reader));
+    Ok(if magic.starts_with(GZIP_MAGIC) {
+        // Archives are often several gzip members glued together
+        Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader)))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Box::new(BufReader::new(zstd::stream::read::Decoder::with_buffer(
+            reader,
+        )?))
+    } else {
+        Box::new(reader)
+    })
+}
+
+// Everything there is to know about a row that could not be parsed, so that it can be
+// found in an input of any size
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: Option<u64>,
+    pub byte: Option<u64>,
+    pub code: &'static str,
+    pub message: String,
+    // The fields of the record as they were read, re-encoded if it's csv
+    pub record: Option<String>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.byte) {
+            (Some(line), Some(byte)) => write!(f, "line {line}, byte {byte}: ")?,
+            (Some(line), None) => write!(f, "line {line}: ")?,
+            _ => {}
+        }
+        write!(f, "{} [{}]", self.message, self.code)?;
+        if let Some(record) = &self.record {
+            write!(f, ", record: {record}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// The columns the deserializer knows, in the order of a standard file
+const COLUMNS: [&str; 5] = ["type", "client", "tx", "amount", "timestamp"];
+// Whatever a file calls them, these have to be there
+const REQUIRED_COLUMNS: usize = 3;
+
+// Where a column is found, either by its name in the header or by its position
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Column {
+    // Counting from 0
+    Position(usize),
+    Name(String),
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Column::Position(position) => write!(f, "at position {position}"),
+            Column::Name(name) => write!(f, "named {name}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColumnMapping {
+    #[serde(rename = "type")]
+    pub kind: Column,
+    pub client: Column,
+    pub tx: Column,
+    pub amount: Column,
+    pub timestamp: Column,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        let [kind, client, tx, amount, timestamp] = COLUMNS.map(|name| Column::Name(name.into()));
+        ColumnMapping {
+            kind,
+            client,
+            tx,
+            amount,
+            timestamp,
+        }
+    }
+}
+
+// How the csv files of a partner look like. The default is the standard file, comma
+// separated with a header naming the columns
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CsvDialect {
+    pub delimiter: char,
+    pub has_headers: bool,
+    pub quoting: bool,
+    pub quote: char,
+    // Whether the records may have a different number of fields, missing ones are empty
+    pub flexible: bool,
+    // Lines starting with it are skipped
+    pub comment: Option<char>,
+    pub columns: ColumnMapping,
+    // Transaction types as the file has them, to the ones the engine knows, e.g. DEP = "deposit"
+    pub types: BTreeMap<String, String>,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        CsvDialect {
+            delimiter: ',',
+            has_headers: true,
+            quoting: true,
+            quote: '"',
+            flexible: false,
+            comment: None,
+            columns: ColumnMapping::default(),
+            types: BTreeMap::new(),
+        }
+    }
+}
+
+impl CsvDialect {
+    fn reader_builder(&self) -> anyhow::Result<ReaderBuilder> {
+        let mut builder = ReaderBuilder::new();
+        builder
+            .trim(csv::Trim::All)
+            .delimiter(ascii("delimiter", self.delimiter)?)
+            .has_headers(self.has_headers)
+            .quoting(self.quoting)
+            .quote(ascii("quote", self.quote)?)
+            .flexible(self.flexible)
+            .comment(
+                self.comment
+                    .map(|comment| ascii("comment", comment))
+                    .transpose()?,
+            );
+        Ok(builder)
+    }
+
+    // Finds the columns in the header, a file without one is taken to have the standard
+    // layout. Missing required columns are fatal, there's no point going through the rows
+    fn projection(&self, headers: &csv::ByteRecord) -> anyhow::Result<Projection> {
+        let ColumnMapping {
+            kind,
+            client,
+            tx,
+            amount,
+            timestamp,
+        } = &self.columns;
+        let mut projection = Projection {
+            columns: [None; COLUMNS.len()],
+            headers: csv::ByteRecord::new(),
+            delimiter: ascii("delimiter", self.delimiter)?,
+            types: self
+                .types
+                .iter()
+                .map(|(alias, name)| (alias.as_bytes().to_vec(), name.as_bytes().to_vec()))
+                .collect(),
+        };
+        for (index, column) in [kind, client, tx, amount, timestamp]
+            .into_iter()
+            .enumerate()
+        {
+            let position = match column {
+                Column::Position(position) => Some(*position),
+                Column::Name(name) if self.has_headers => {
+                    headers.iter().position(|header| header == name.as_bytes())
+                }
+                Column::Name(name) => COLUMNS.iter().position(|column| column == name),
+            };
+            match position {
+                Some(position) => {
+                    projection.columns[index] = Some(position);
+                    projection.headers.push_field(COLUMNS[index].as_bytes());
+                }
+                None if index < REQUIRED_COLUMNS => {
+                    anyhow::bail!(
+                        "The {} column, {column}, is not in the input",
+                        COLUMNS[index]
+                    )
+                }
+                None => {}
+            }
+        }
+        Ok(projection)
+    }
+}
+
+fn ascii(setting: &str, value: char) -> anyhow::Result<u8> {
+    u8::try_from(value)
+        .ok()
+        .filter(u8::is_ascii)
+        .ok_or_else(|| anyhow::anyhow!("The csv {setting} has to be an ascii character"))
+}
+
+// Where the columns the deserializer knows are in a record, and what to make of them
+struct Projection {
+    // Positions in the record, in the standard order
+    columns: [Option<usize>; COLUMNS.len()],
+    // Of the columns that are there
+    headers: csv::ByteRecord,
+    delimiter: u8,
+    types: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Projection {
+    fn field<'a>(&'a self, record: &'a csv::ByteRecord, index: usize) -> Option<&'a [u8]> {
+        let field = record.get(self.columns[index]?).unwrap_or_default();
+        // The type is the only column with aliases
+        Some(match index {
+            0 => self.types.get(field).map_or(field, Vec::as_slice),
+            _ => field,
+        })
+    }
+
+    // The fast path, straight from the bytes without serde and without allocating anything.
+    // Whatever it is not sure about is left to the deserializer, which knows how to report it
+    fn parse(&self, record: &csv::ByteRecord) -> Option<Transaction> {
+        let kind = std::str::from_utf8(self.field(record, 0)?).ok()?;
+        let cid = ClientId::from_bytes(self.field(record, 1)?)?;
+        let tid = TransactionId::from_bytes(self.field(record, 2)?)?;
+        let amount = match self.field(record, 3) {
+            None | Some(b"") => None,
+            Some(amount) => Some(parse_decimal(amount)?),
+        };
+        let timestamp = match self.field(record, 4) {
+            None | Some(b"") => None,
+            Some(timestamp) => Some(parse_integer(timestamp)?),
+        };
+        Transaction::from_fields(kind, cid, tid, amount, timestamp).ok()
+    }
+
+    fn deserialize(
+        &self,
+        record: &csv::ByteRecord,
+        projected: &mut csv::ByteRecord,
+        line: u64,
+    ) -> Result<Transaction, ParseError> {
+        projected.clear();
+        for index in 0..COLUMNS.len() {
+            if let Some(field) = self.field(record, index) {
+                projected.push_field(field);
+            }
+        }
+        projected.set_position(record.position().cloned());
+        projected
+            .deserialize::<CsvTransaction>(Some(&self.headers))
+            .map(|CsvTransaction(transaction)| transaction)
+            .map_err(|error| {
+                let mut parse_error = csv_error(error);
+                parse_error.line = Some(line);
+                parse_error.record = Some(encode_record(record, self.delimiter));
+                parse_error
+            })
+    }
+}
+
+// Plain digits only, anything fancier is up to the deserializer
+pub(crate) fn parse_integer(bytes: &[u8]) -> Option<u64> {
+    if bytes.is_empty() {
+        return None;
+    }
+    bytes.iter().try_fold(0u64, |value, &byte| {
+        let digit = byte.checked_sub(b'0').filter(|digit| *digit <= 9)?;
+        value.checked_mul(10)?.checked_add(digit as u64)
+    })
+}
+
+// Plain digits with an optional decimal point in between, e.g. 12 or 0.5000. Anything
+// fancier, like a sign, an exponent or more digits than a decimal could take for sure,
+// is up to the deserializer
+fn parse_decimal(bytes: &[u8]) -> Option<Decimal> {
+    const MAX_DIGITS: u32 = 28;
+    let (integer, fraction) = match bytes.iter().position(|byte| *byte == b'.') {
+        Some(point) => (&bytes[..point], Some(&bytes[point + 1..])),
+        None => (bytes, None),
+    };
+    if integer.is_empty() || fraction.is_some_and(<[u8]>::is_empty) {
+        return None;
+    }
+    let mut mantissa = 0i128;
+    let mut digits = 0;
+    for &byte in integer.iter().chain(fraction.unwrap_or_default()) {
+        let digit = byte.checked_sub(b'0').filter(|digit| *digit <= 9)?;
+        mantissa = mantissa * 10 + digit as i128;
+        digits += 1;
+        if digits > MAX_DIGITS {
+            return None;
+        }
+    }
+    let scale = fraction.map_or(0, <[u8]>::len) as u32;
+    Decimal::try_from_i128_with_scale(mantissa, scale).ok()
+}
+
+pub fn read_csv(reader: impl Read + 'static, dialect: &CsvDialect) -> anyhow::Result<Transactions> {
+    let lines = Rc::new(RefCell::new(LineIndex::default()));
+    let mut reader = dialect.reader_builder()?.from_reader(LineCounter {
+        reader,
+        lines: lines.clone(),
+    });
+    let headers = reader.byte_headers()?.clone();
+    // An empty file has no header to find the columns in, and nothing else either
+    if dialect.has_headers && headers.is_empty() {
+        return Ok(Box::new(std::iter::empty()));
+    }
+    let projection = dialect.projection(&headers)?;
+    // Both records are reused for every row, the second one by the deserializer only
+    let mut record = csv::ByteRecord::new();
+    let mut projected = csv::ByteRecord::new();
+    Ok(Box::new(std::iter::from_fn(move || {
+        let result = reader.read_byte_record(&mut record);
+        // The last byte of the record is either its terminator or the last one of the input
+        let end = reader.position().byte().saturating_sub(1);
+        let line = lines.borrow_mut().line_of(end);
+        let item = match result {
+            Ok(false) => return None,
+            Ok(true) => {
+                // Quoted fields may span lines, but for the newlines trimmed off their ends
+                let line = line - count_newlines(record.as_slice());
+                let result = match projection.parse(&record) {
+                    Some(transaction) => Ok(transaction),
+                    None => projection
+                        .deserialize(&record, &mut projected, line)
+                        .map_err(Into::into),
+                };
+                (line, result)
+            }
+            Err(error) => {
+                let mut error = csv_error(error);
+                error.line = Some(line);
+                (line, Err(error.into()))
+            }
+        };
+        Some(item)
+    })))
+}
+
+// Where the lines of the input are, for the records to tell which one they start on. The csv
+// reader counts lines as well, but it counts blank lines and comments in with the record
+// after them, and the end of a crlf line only once the next record is read
+#[derive(Debug, Default)]
+struct LineIndex {
+    // Offsets of the newlines in the last buffer read from the input, those before `next`
+    // already passed by the parser
+    newlines: Vec<u64>,
+    next: usize,
+    passed: u64,
+    read: u64,
+}
+
+impl LineIndex {
+    // Counting from 1, the offsets asked for may only grow
+    fn line_of(&mut self, offset: u64) -> u64 {
+        let passed = self.newlines[self.next..]
+            .iter()
+            .take_while(|newline| **newline < offset)
+            .count();
+        self.next += passed;
+        self.passed += passed as u64;
+        self.passed + 1
+    }
+}
+
+struct LineCounter<R> {
+    reader: R,
+    lines: Rc<RefCell<LineIndex>>,
+}
+
+impl<R: Read> Read for LineCounter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.reader.read(buf)?;
+        let mut lines = self.lines.borrow_mut();
+        let start = lines.read;
+        let next = lines.next;
+        lines.newlines.drain(..next);
+        lines.next = 0;
+        lines
+            .newlines
+            .extend(memchr::memchr_iter(b'\n', &buf[..count]).map(|offset| start + offset as u64));
+        lines.read += count as u64;
+        Ok(count)
+    }
+}
+
+fn count_newlines(bytes: &[u8]) -> u64 {
+    bytes.iter().filter(|byte| **byte == b'\n').count() as u64
+}
+
+fn csv_error(error: csv::Error) -> ParseError {
+    let position = error.position();
+    let line = position.map(csv::Position::line);
+    let byte = position.map(csv::Position::byte);
+    let (code, message) = match error.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => {
+            let code = match err.kind() {
+                csv::DeserializeErrorKind::Message(message) => error_code(message),
+                csv::DeserializeErrorKind::UnexpectedEndOfRow => "missing-field",
+                csv::DeserializeErrorKind::InvalidUtf8(_) => "invalid-utf8",
+                _ => "invalid-value",
+            };
+            let message = match err.field() {
+                Some(field) => format!("field {field}: {}", err.kind()),
+                None => err.kind().to_string(),
+            };
+            (code, message)
+        }
+        csv::ErrorKind::UnequalLengths {
+            expected_len, len, ..
+        } => (
+            "wrong-field-count",
+            format!("found {len} fields, expected {expected_len}"),
+        ),
+        csv::ErrorKind::Utf8 { err, .. } => ("invalid-utf8", err.to_string()),
+        _ => ("unreadable", error.to_string()),
+    };
+    ParseError {
+        line,
+        byte,
+        code,
+        message,
+        record: None,
+    }
+}
+
+// Puts the fields back together the way csv would write them, quoting whatever needs it
+fn encode_record(record: &csv::ByteRecord, delimiter: u8) -> String {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(Vec::new());
+    let encoded = writer
+        .write_byte_record(record)
+        .ok()
+        .and_then(|()| writer.into_inner().ok())
+        .unwrap_or_default();
+    String::from_utf8_lossy(&encoded).trim_end().to_string()
+}
+
+// Goes through the same deserializer as csv does, so the validation is the same as well.
+// Amounts may be either json numbers or strings, both are taken at full precision
+pub fn read_ndjson(reader: impl BufRead + 'static) -> Transactions {
+    Box::new(reader.lines().enumerate().filter_map(|(index, line)| {
+        let line_number = index as u64 + 1;
+        let result = match line {
+            Ok(line) if line.trim().is_empty() => return None,
+            Ok(line) => serde_json::from_str::<Transaction>(&line).map_err(|error| {
+                // The position is in the line only, which is known already
+                let message = error.to_string();
+                let message = message
+                    .strip_suffix(&format!(
+                        " at line {} column {}",
+                        error.line(),
+                        error.column()
+                    ))
+                    .unwrap_or(&message)
+                    .to_string();
+                let code = match error.classify() {
+                    serde_json::error::Category::Data => error_code(&message),
+                    serde_json::error::Category::Io => "unreadable",
+                    _ => "invalid-json",
+                };
+                ParseError {
+                    line: Some(line_number),
+                    byte: None,
+                    code,
+                    message,
+                    record: Some(line),
+                }
+                .into()
+            }),
+            Err(error) => Err(ParseError {
+                line: Some(line_number),
+                byte: None,
+                code: "unreadable",
+                message: error.to_string(),
+                record: None,
+            }
+            .into()),
+        };
+        Some((line_number, result))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TransactionKind, id::tid};
+    use rust_decimal_macros::dec;
+
+    fn parse_ndjson(input: &'static str) -> Vec<anyhow::Result<Transaction>> {
+        read_ndjson(input.as_bytes())
+            .map(|(_, result)| result)
+            .collect()
+    }
+
+    #[test]
+    fn format_from_extension() {
+        assert_eq!(
+            InputFormat::from_path(Path::new("in.ndjson")),
+            InputFormat::Ndjson
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("in.jsonl")),
+            InputFormat::Ndjson
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("in.csv")),
+            InputFormat::Csv
+        );
+        assert_eq!(InputFormat::from_path(Path::new("in")), InputFormat::Csv);
+    }
+
+    #[test]
+    fn ndjson_matches_csv() {
+        let csv: Vec<_> = read_csv(
+            "type,client,tx,amount\n\
+             deposit,1,1,1.0000000001\n\
+             dispute,1,1,\n"
+                .as_bytes(),
+            &CsvDialect::default(),
+        )
+        .unwrap()
+        .map(|(_, result)| format!("{:?}", result.unwrap()))
+        .collect();
+        let ndjson: Vec<_> = parse_ndjson(
+            r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1.0000000001}
+
+            {"type": "dispute", "client": 1, "tx": 1}"#,
+        )
+        .into_iter()
+        .map(|result| format!("{:?}", result.unwrap()))
+        .collect();
+
+        assert_eq!(csv, ndjson);
+    }
+
+    #[test]
+    fn ndjson_amount_as_string() {
+        let transactions = parse_ndjson(
+            r#"{"type": "withdrawal", "client": 2, "tx": 3, "amount": "2.5", "timestamp": 7}"#,
+        );
+
+        let transaction = transactions[0].as_ref().unwrap();
+        assert_eq!(transaction.timestamp, Some(7));
+        assert!(matches!(
+            transaction.kind,
+            TransactionKind::Withdrawal { amount } if amount == dec!(2.5)
+        ));
+    }
+
+    #[test]
+    fn ndjson_validation_errors() {
+        let transactions = parse_ndjson(
+            "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": -1}\n\
+             {\"type\": \"dispute\", \"client\": 1, \"tx\": 1, \"amount\": 1}\n\
+             not json\n\
+             {\"type\": \"resolve\", \"client\": 1, \"tx\": 1, \"amount\": null}",
+        );
+
+        let errors: Vec<_> = transactions
+            .iter()
+            .map(|result| result.as_ref().err().map(|error| error.to_string()))
+            .collect();
+        assert!(
+            errors[0]
+                .as_ref()
+                .unwrap()
+                .starts_with("line 1: deposit amount must be positive")
+        );
+        assert!(
+            errors[1]
+                .as_ref()
+                .unwrap()
+                .starts_with("line 2: dispute must not have amount")
+        );
+        assert!(errors[2].as_ref().unwrap().starts_with("line 3: "));
+        assert!(errors[3].is_none());
+    }
+
+    fn parse_error(result: &anyhow::Result<Transaction>) -> &ParseError {
+        result.as_ref().unwrap_err().downcast_ref().unwrap()
+    }
+
+    #[test]
+    fn csv_errors_are_located() {
+        let transactions: Vec<_> = read_csv(
+            "type,client,tx,amount\n\
+             deposit,1,1,5\n\
+             deposit, 1,2,-5\n\
+             bogus,1,3,\n\
+             withdrawal,1,5\n\
+             dispute,1,1,\"1,5\"\n"
+                .as_bytes(),
+            &CsvDialect::default(),
+        )
+        .unwrap()
+        .map(|(_, result)| result)
+        .collect();
+
+        assert!(transactions[0].is_ok());
+        assert_eq!(
+            parse_error(&transactions[1]),
+            &ParseError {
+                line: Some(3),
+                byte: Some(36),
+                code: "non-positive-amount",
+                message: "deposit amount must be positive".to_string(),
+                record: Some("deposit,1,2,-5".to_string()),
+            }
+        );
+        assert_eq!(parse_error(&transactions[2]).code, "unknown-type");
+        assert_eq!(parse_error(&transactions[3]).code, "wrong-field-count");
+        assert_eq!(parse_error(&transactions[3]).line, Some(5));
+        assert_eq!(
+            parse_error(&transactions[4]).record.as_deref(),
+            Some("dispute,1,1,\"1,5\"")
+        );
+        assert_eq!(
+            transactions[1].as_ref().unwrap_err().to_string(),
+            "line 3, byte 36: deposit amount must be positive [non-positive-amount], \
+             record: deposit,1,2,-5"
+        );
+    }
+
+    #[test]
+    fn ndjson_error_codes() {
+        let transactions = parse_ndjson(
+            "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1}\n\
+             {\"type\": \"deposit\",\n\
+             {\"type\": \"void\", \"tx\": 1}",
+        );
+
+        let codes: Vec<_> = transactions
+            .iter()
+            .map(|result| parse_error(result).code)
+            .collect();
+        assert_eq!(codes, ["missing-amount", "invalid-json", "missing-field"]);
+        assert_eq!(parse_error(&transactions[2]).line, Some(3));
+        assert_eq!(
+            parse_error(&transactions[2]).record.as_deref(),
+            Some("{\"type\": \"void\", \"tx\": 1}")
+        );
+    }
+
+    #[test]
+    fn csv_dialect_with_column_mapping() {
+        let dialect = CsvDialect {
+            delimiter: ';',
+            comment: Some('#'),
+            columns: ColumnMapping {
+                kind: Column::Name("kind".to_string()),
+                client: Column::Name("customer".to_string()),
+                tx: Column::Position(0),
+                amount: Column::Name("value".to_string()),
+                ..Default::default()
+            },
+            types: [("DEP".to_string(), "deposit".to_string())].into(),
+            ..Default::default()
+        };
+        let transactions: Vec<_> = read_csv(
+            "id;kind;note;customer;value\n\
+             # skipped\n\
+             7;DEP;a;2;1.5\n\
+             8;dispute;b;2;\n"
+                .as_bytes(),
+            &dialect,
+        )
+        .unwrap()
+        .map(|(_, result)| result.unwrap())
+        .collect();
+
+        assert_eq!(transactions.len(), 2);
+        assert_eq!((transactions[0].cid, transactions[0].tid), (2, tid(7)));
+        assert!(matches!(
+            transactions[0].kind,
+            TransactionKind::Deposit { amount } if amount == dec!(1.5)
+        ));
+        assert!(matches!(transactions[1].kind, TransactionKind::Dispute));
+    }
+
+    #[test]
+    fn csv_lines() {
+        let dialect = CsvDialect {
+            comment: Some('#'),
+            ..Default::default()
+        };
+        let transactions: Vec<_> = read_csv(
+            "type,client,tx,amount\r\n\
+             \r\n\
+             deposit,1,1,5\r\n\
+             # note\r\n\
+             deposit,1,2,\"5\"\r\n\
+             bogus,1,3,\r\n\
+             deposit,1,5,\"1\r\n\
+             1\"\r\n\
+             withdrawal,1,4,1"
+                .as_bytes(),
+            &dialect,
+        )
+        .unwrap()
+        .collect();
+
+        let lines: Vec<_> = transactions.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [3, 5, 6, 7, 9]);
+        assert_eq!(parse_error(&transactions[2].1).line, Some(6));
+        assert_eq!(parse_error(&transactions[3].1).line, Some(7));
+        assert!(transactions[4].1.is_ok());
+    }
+
+    #[test]
+    fn csv_without_headers() {
+        let dialect = CsvDialect {
+            has_headers: false,
+            flexible: true,
+            ..Default::default()
+        };
+        let transactions: Vec<_> = read_csv(
+            "deposit,1,1,2\nwithdrawal,1,2,1,7,x\ndispute,1,1\n".as_bytes(),
+            &dialect,
+        )
+        .unwrap()
+        .map(|(_, result)| result)
+        .collect();
+        assert_eq!(transactions.len(), 3);
+        assert_eq!(transactions[1].as_ref().unwrap().timestamp, Some(7));
+        assert!(matches!(
+            transactions[2].as_ref().unwrap().kind,
+            TransactionKind::Dispute
+        ));
+    }
+
+    #[test]
+    fn csv_missing_column_is_fatal() {
+        let error = read_csv("type,client,amount\n".as_bytes(), &CsvDialect::default())
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "The tx column, named tx, is not in the input"
+        );
+        assert!(read_csv("".as_bytes(), &CsvDialect::default()).is_ok());
+
+        let dialect = CsvDialect {
+            delimiter: '€',
+            ..Default::default()
+        };
+        assert!(read_csv("".as_bytes(), &dialect).is_err());
+    }
+
+    // Hands out a single byte at a time, like a slow pipe might
+    struct Trickle(io::Cursor<Vec<u8>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn compressed_input() {
+        let input = "type,client,tx,amount\ndeposit,1,1,2\n";
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        std::io::Write::write_all(&mut gzip, input.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::encode_all(input.as_bytes(), 0).unwrap();
+
+        for compressed in [gzip, zstd, input.as_bytes().to_vec()] {
+            let mut decompressed = String::new();
+            decompress(io::Cursor::new(compressed.clone()))
+                .unwrap()
+                .read_to_string(&mut decompressed)
+                .unwrap();
+            assert_eq!(decompressed, input);
+
+            let mut trickled = String::new();
+            decompress(Trickle(io::Cursor::new(compressed)))
+                .unwrap()
+                .read_to_string(&mut trickled)
+                .unwrap();
+            assert_eq!(trickled, input);
+        }
+        for short in ["", "a"] {
+            let mut decompressed = String::new();
+            decompress(io::Cursor::new(short.as_bytes().to_vec()))
+                .unwrap()
+                .read_to_string(&mut decompressed)
+                .unwrap();
+            assert_eq!(decompressed, short);
+        }
+        assert_eq!(
+            InputFormat::from_path(Path::new("in.jsonl.gz")),
+            InputFormat::Ndjson
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("in.csv.zst")),
+            InputFormat::Csv
+        );
+    }
+
+    #[test]
+    fn fast_path_matches_deserializer() {
+        let headers = csv::ByteRecord::from(vec!["type", "client", "tx", "amount", "timestamp"]);
+        let projection = CsvDialect::default().projection(&headers).unwrap();
+        let mut projected = csv::ByteRecord::new();
+        let rows = [
+            vec!["deposit", "1", "1", "1.5", ""],
+            vec!["withdrawal", "65535", "4294967295", "0.0001", "17"],
+            vec!["deposit", "007", "1", "12.3400", ""],
+            vec!["deposit", "1", "1", "0.3400", ""],
+            vec!["dispute", "1", "1", "", ""],
+            vec!["capture", "1", "1", "", ""],
+        ];
+        for row in rows {
+            let record = csv::ByteRecord::from(row);
+            let fast = projection.parse(&record).unwrap();
+            let deserialized = projection.deserialize(&record, &mut projected, 0).unwrap();
+            assert_eq!(
+                (fast.cid, fast.tid, fast.timestamp),
+                (deserialized.cid, deserialized.tid, deserialized.timestamp)
+            );
+            assert_eq!(fast.kind, deserialized.kind);
+        }
+
+        // Both keep every digit, whatever is too long for the fast path is taken exactly by
+        // the deserializer, or refused should it not fit without rounding
+        let mut amount = |amount| {
+            let record = csv::ByteRecord::from(vec!["deposit", "1", "1", amount, ""]);
+            let fast = projection
+                .parse(&record)
+                .map(|transaction| transaction.amount());
+            let deserialized = projection
+                .deserialize(&record, &mut projected, 0)
+                .map(|transaction| transaction.amount())
+                .map_err(|error| error.code);
+            (fast, deserialized)
+        };
+        assert_eq!(
+            amount("1234567890123456789012345678"),
+            (
+                Some(Some(dec!(1234567890123456789012345678))),
+                Ok(Some(dec!(1234567890123456789012345678)))
+            )
+        );
+        assert_eq!(
+            amount("1.2345678901234567890123456789"),
+            (None, Ok(Some(dec!(1.2345678901234567890123456789))))
+        );
+        assert_eq!(amount("+0.1"), (None, Ok(Some(dec!(0.1)))));
+        assert_eq!(amount("1e3"), (None, Ok(Some(dec!(1000)))));
+        assert_eq!(
+            amount("0.00000000000000000000000000001"),
+            (None, Err("invalid-value"))
+        );
+        assert_eq!(
+            amount("123456789012345678901234567890"),
+            (None, Err("invalid-value"))
+        );
+    }
+
+    #[test]
+    fn fast_path_leaves_the_rest_to_the_deserializer() {
+        let headers = csv::ByteRecord::from(vec!["type", "client", "tx", "amount"]);
+        let projection = CsvDialect::default().projection(&headers).unwrap();
+        let rows = [
+            vec!["deposit", "1", "1", "+1"],
+            vec!["deposit", "1", "1", "-1"],
+            vec!["deposit", "1", "1", "1e3"],
+            vec!["deposit", "1", "1", ".5"],
+            vec!["deposit", "1", "1", "5."],
+            vec!["deposit", "1", "1", "1.2.3"],
+            vec!["deposit", "1", "1", "12345678901234567890123456789"],
+            vec!["deposit", "18446744073709551616", "1", "1"],
+            vec!["deposit", "+1", "1", "1"],
+            vec!["deposit", "1", "1", ""],
+            vec!["dispute", "1", "1", "1"],
+            vec!["bogus", "1", "1", ""],
+        ];
+        for row in rows {
+            assert!(projection.parse(&csv::ByteRecord::from(row)).is_none());
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use std::{collections::BTreeMap, fmt};
+
+use rust_decimal::Decimal;
+
+use crate::{ClientId, rejection::Rejection};
+
+// Every client owns two ledger accounts, both are liabilities of the house: the funds the
+// client could spend and the funds frozen for them. The house account is the other side of
+// whatever comes in or goes out of the system
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LedgerAccount {
+    House,
+    Available(ClientId),
+    Held(ClientId),
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerAccount::House => write!(f, "house"),
+            LedgerAccount::Available(cid) => write!(f, "available:{cid}"),
+            LedgerAccount::Held(cid) => write!(f, "held:{cid}"),
+        }
+    }
+}
+
+// Moves the amount out of the debit account into the credit one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Posting {
+    pub debit: LedgerAccount,
+    pub credit: LedgerAccount,
+    pub amount: Decimal,
+}
+
+// The debits and credits are the turnover of the account, they are there for the trial
+// balance only and saturate rather than getting in the way. What is limited is the balance,
+// which as the accounts are liabilities grows with credits
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LedgerTotals {
+    pub debits: Decimal,
+    pub credits: Decimal,
+    pub balance: Decimal,
+}
+
+#[derive(Debug, Default)]
+pub struct Ledger {
+    accounts: BTreeMap<LedgerAccount, LedgerTotals>,
+}
+
+// Totals of the accounts some postings are about to touch, not in the ledger just yet
+#[derive(Debug, Default)]
+pub struct PreparedPostings {
+    accounts: Vec<(LedgerAccount, LedgerTotals)>,
+}
+
+impl PreparedPostings {
+    pub fn balance(&self, ledger: &Ledger, account: LedgerAccount) -> Decimal {
+        self.accounts
+            .iter()
+            .find(|(prepared, _)| *prepared == account)
+            .map(|(_, totals)| totals.balance)
+            .unwrap_or_else(|| ledger.balance(account))
+    }
+}
+
+impl Ledger {
+    // Works out the new totals of the accounts the postings touch, making sure the balances
+    // of the clients still fit into a Decimal. The house is the other side of all the clients
+    // at once, limiting its balance would let one client holding a lot keep the others from
+    // depositing anything, so it is not kept here at all, see house_balance. Nothing gets
+    // posted until commit, so a transaction either lands in the ledger as a whole or not at all
+    pub fn prepare(&self, postings: &[Posting]) -> Result<PreparedPostings, Rejection> {
+        let mut prepared = PreparedPostings::default();
+        // Nothing moves with a zero posting, e.g. what is left of a fully captured
+        // authorization, so it would only clutter the trial balance
+        for posting in postings.iter().filter(|posting| !posting.amount.is_zero()) {
+            for (account, is_debit) in [(posting.debit, true), (posting.credit, false)] {
+                let index = match prepared
+                    .accounts
+                    .iter()
+                    .position(|(prepared, _)| *prepared == account)
+                {
+                    Some(index) => index,
+                    None => {
+                        let totals = self.accounts.get(&account).copied().unwrap_or_default();
+                        prepared.accounts.push((account, totals));
+                        prepared.accounts.len() - 1
+                    }
+                };
+                let totals = &mut prepared.accounts[index].1;
+                let change = if is_debit {
+                    totals.debits = totals.debits.saturating_add(posting.amount);
+                    -posting.amount
+                } else {
+                    totals.credits = totals.credits.saturating_add(posting.amount);
+                    posting.amount
+                };
+                if account != LedgerAccount::House {
+                    totals.balance = totals
+                        .balance
+                        .checked_add(change)
+                        .ok_or(Rejection::Overflow)?;
+                }
+            }
+        }
+
+        Ok(prepared)
+    }
+
+    pub fn commit(&mut self, prepared: PreparedPostings) {
+        self.accounts.extend(prepared.accounts);
+    }
+
+    pub fn balance(&self, account: LedgerAccount) -> Decimal {
+        if account == LedgerAccount::House {
+            return self.house_balance();
+        }
+        self.accounts
+            .get(&account)
+            .map(|totals| totals.balance)
+            .unwrap_or_default()
+    }
+
+    // The house owes what the clients are owed, so its balance is what is left of theirs.
+    // Worked out in account order, it comes out the same however the postings were split up,
+    // and saturates should the clients hold more than a Decimal does
+    fn house_balance(&self) -> Decimal {
+        -self
+            .accounts
+            .iter()
+            .filter(|(account, _)| **account != LedgerAccount::House)
+            .fold(Decimal::ZERO, |sum, (_, totals)| {
+                sum.saturating_add(totals.balance)
+            })
+    }
+
+    // Adds up the totals of another ledger, the one of a shard. Client accounts are never in
+    // two shards at once, but the house account is in all of them. Its balance is not kept
+    // and the debits and credits saturate, so nothing can go wrong here
+    pub fn merge(&mut self, other: Ledger) {
+        for (account, totals) in other.accounts {
+            let sum = self.accounts.entry(account).or_default();
+            sum.debits = sum.debits.saturating_add(totals.debits);
+            sum.credits = sum.credits.saturating_add(totals.credits);
+            sum.balance = sum.balance.saturating_add(totals.balance);
+        }
+    }
+
+    // Debit and credit totals of every account that has ever been posted to, in account order
+    pub fn trial_balance(&self) -> impl Iterator<Item = (LedgerAccount, LedgerTotals)> {
+        self.accounts.iter().map(|(account, totals)| {
+            let balance = match account {
+                LedgerAccount::House => self.house_balance(),
+                _ => totals.balance,
+            };
+            (*account, LedgerTotals { balance, ..*totals })
+        })
+    }
+
+    // Sum of the totals over all the accounts. Every posting adds the same amount to both
+    // sides, so the debits must always match the credits, money is neither made nor lost.
+    // Once the turnover gets too large to add up, there is nothing to compare anymore
+    pub fn totals(&self) -> Option<LedgerTotals> {
+        self.trial_balance()
+            .try_fold(LedgerTotals::default(), |sum, (_, totals)| {
+                Some(LedgerTotals {
+                    debits: sum.debits.checked_add(totals.debits)?,
+                    credits: sum.credits.checked_add(totals.credits)?,
+                    balance: sum.balance.checked_add(totals.balance)?,
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn post(ledger: &mut Ledger, postings: &[Posting]) -> Result<(), Rejection> {
+        let prepared = ledger.prepare(postings)?;
+        ledger.commit(prepared);
+        Ok(())
+    }
+
+    #[test]
+    fn postings_stay_balanced() {
+        let mut ledger = Ledger::default();
+        post(
+            &mut ledger,
+            &[Posting {
+                debit: LedgerAccount::House,
+                credit: LedgerAccount::Available(1),
+                amount: dec!(10),
+            }],
+        )
+        .unwrap();
+        post(
+            &mut ledger,
+            &[Posting {
+                debit: LedgerAccount::Available(1),
+                credit: LedgerAccount::Held(1),
+                amount: dec!(4),
+            }],
+        )
+        .unwrap();
+        post(
+            &mut ledger,
+            &[Posting {
+                debit: LedgerAccount::Held(2),
+                credit: LedgerAccount::Available(2),
+                amount: dec!(0),
+            }],
+        )
+        .unwrap();
+
+        assert_eq!(ledger.balance(LedgerAccount::House), dec!(-10));
+        assert_eq!(ledger.balance(LedgerAccount::Available(1)), dec!(6));
+        assert_eq!(ledger.balance(LedgerAccount::Held(1)), dec!(4));
+        assert_eq!(ledger.balance(LedgerAccount::Held(2)), dec!(0));
+
+        let totals = ledger.totals().unwrap();
+        assert_eq!(totals.debits, totals.credits);
+        assert_eq!(
+            ledger
+                .trial_balance()
+                .map(|(account, _)| account)
+                .collect::<Vec<_>>(),
+            vec![
+                LedgerAccount::House,
+                LedgerAccount::Available(1),
+                LedgerAccount::Held(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn overflowing_postings_are_not_committed() {
+        let mut ledger = Ledger::default();
+        let deposit = Posting {
+            debit: LedgerAccount::House,
+            credit: LedgerAccount::Available(1),
+            amount: Decimal::MAX,
+        };
+        post(&mut ledger, &[deposit]).unwrap();
+
+        // The second posting overflows, so the first one must not land either
+        assert_eq!(
+            post(
+                &mut ledger,
+                &[
+                    Posting {
+                        debit: LedgerAccount::Available(1),
+                        credit: LedgerAccount::Held(1),
+                        amount: dec!(1),
+                    },
+                    deposit
+                ]
+            ),
+            Err(Rejection::Overflow)
+        );
+        assert_eq!(ledger.balance(LedgerAccount::Available(1)), Decimal::MAX);
+        assert_eq!(ledger.balance(LedgerAccount::Held(1)), dec!(0));
+    }
+}
@@ -0,0 +1,24 @@
+pub mod account;
+pub mod audit;
+pub mod budget;
+pub mod config;
+pub mod diff;
+pub mod id;
+pub mod input;
+pub mod ledger;
+pub mod limits;
+pub mod output;
+pub mod payments;
+pub mod reconcile;
+pub mod rejection;
+pub mod rules;
+pub mod shard;
+pub mod state;
+pub mod statement;
+pub mod trace;
+pub mod transaction;
+pub mod validate;
+
+pub use account::Account;
+pub use id::{ClientId, TransactionId};
+pub use transaction::{Action, ActionKind, Transaction, TransactionKind};
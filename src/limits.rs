@@ -0,0 +1,187 @@
+use std::collections::{HashMap, VecDeque};
+
+use csv::ReaderBuilder;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::{ClientId, rejection::Rejection};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowUnit {
+    // The window spans the last N input rows, regardless of who they belong to
+    Rows,
+    // The window spans the last N seconds, according to the transaction timestamps
+    Seconds,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VelocityLimit {
+    pub window: u64,
+    pub unit: WindowUnit,
+    pub max_count: Option<usize>,
+    pub max_amount: Option<Decimal>,
+}
+
+// Successful withdrawals of a single client, still within the window of its limit.
+// Each entry is a (point, amount) pair, where the point is either the row number
+// or the timestamp, depending on the unit of the limit
+#[derive(Debug, Default, Clone)]
+pub struct WithdrawalWindow {
+    entries: VecDeque<(u64, Decimal)>,
+}
+
+impl VelocityLimit {
+    // Where the given transaction sits on the axis this limit is measured along
+    pub fn point(&self, row: u64, timestamp: Option<u64>) -> Result<u64, Rejection> {
+        match self.unit {
+            WindowUnit::Rows => Ok(row),
+            WindowUnit::Seconds => timestamp.ok_or(Rejection::MissingTimestamp),
+        }
+    }
+
+    // Checks whether one more withdrawal of the given amount at the given point still
+    // fits the limit. Does not record anything, see WithdrawalWindow::record for that
+    pub fn check(
+        &self,
+        window: &mut WithdrawalWindow,
+        point: u64,
+        amount: Decimal,
+    ) -> Result<(), Rejection> {
+        // Dropping everything that has already slid out of the window
+        while let Some(&(oldest, _)) = window.entries.front() {
+            if oldest.saturating_add(self.window) > point {
+                break;
+            }
+            window.entries.pop_front();
+        }
+
+        if let Some(max_count) = self.max_count
+            && window.entries.len() + 1 > max_count
+        {
+            return Err(Rejection::VelocityCountExceeded);
+        }
+
+        if let Some(max_amount) = self.max_amount {
+            // Whatever is too large to add up is way over the limit anyway
+            let spent = window
+                .entries
+                .iter()
+                .try_fold(amount, |spent, (_, amount)| spent.checked_add(*amount));
+            if spent.is_none_or(|spent| spent > max_amount) {
+                return Err(Rejection::VelocityAmountExceeded);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl WithdrawalWindow {
+    pub fn record(&mut self, point: u64, amount: Decimal) {
+        self.entries.push_back((point, amount));
+    }
+}
+
+// Reads per client limits from a csv file, which looks like this:
+//
+// client,window,unit,max_count,max_amount
+// 1,10,rows,3,
+// 2,3600,seconds,,500.0
+//
+// Either of max_count or max_amount can be omitted, but not both
+pub fn load_limits(path: &str) -> anyhow::Result<HashMap<ClientId, VelocityLimit>> {
+    #[derive(Deserialize)]
+    struct LimitCSVRow {
+        client: ClientId,
+        window: u64,
+        unit: WindowUnit,
+        max_count: Option<usize>,
+        max_amount: Option<Decimal>,
+    }
+
+    let mut limits = HashMap::new();
+    for result in ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?
+        .deserialize::<LimitCSVRow>()
+    {
+        let row = result?;
+        if row.window == 0 {
+            anyhow::bail!("limit for client {} has an empty window", row.client);
+        }
+        if row.max_count.is_none() && row.max_amount.is_none() {
+            anyhow::bail!("limit for client {} restricts nothing", row.client);
+        }
+        let limit = VelocityLimit {
+            window: row.window,
+            unit: row.unit,
+            max_count: row.max_count,
+            max_amount: row.max_amount,
+        };
+        if limits.insert(row.client, limit).is_some() {
+            anyhow::bail!("client {} has more than one limit", row.client);
+        }
+    }
+
+    Ok(limits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn count_limit_slides_with_rows() {
+        let limit = VelocityLimit {
+            window: 3,
+            unit: WindowUnit::Rows,
+            max_count: Some(1),
+            max_amount: None,
+        };
+        let mut window = WithdrawalWindow::default();
+
+        assert_eq!(limit.check(&mut window, 0, dec!(1)), Ok(()));
+        window.record(0, dec!(1));
+        assert_eq!(
+            limit.check(&mut window, 2, dec!(1)),
+            Err(Rejection::VelocityCountExceeded)
+        );
+        assert_eq!(limit.check(&mut window, 3, dec!(1)), Ok(()));
+    }
+
+    #[test]
+    fn amount_limit_sums_the_window() {
+        let limit = VelocityLimit {
+            window: 60,
+            unit: WindowUnit::Seconds,
+            max_count: None,
+            max_amount: Some(dec!(100)),
+        };
+        let mut window = WithdrawalWindow::default();
+
+        window.record(1000, dec!(60));
+        assert_eq!(limit.check(&mut window, 1010, dec!(40)), Ok(()));
+        window.record(1010, dec!(40));
+        assert_eq!(
+            limit.check(&mut window, 1020, dec!(0.01)),
+            Err(Rejection::VelocityAmountExceeded)
+        );
+        // The first withdrawal is out of the window by now
+        assert_eq!(limit.check(&mut window, 1060, dec!(60)), Ok(()));
+    }
+
+    #[test]
+    fn time_limit_requires_timestamp() {
+        let limit = VelocityLimit {
+            window: 60,
+            unit: WindowUnit::Seconds,
+            max_count: Some(1),
+            max_amount: None,
+        };
+
+        assert_eq!(limit.point(7, None), Err(Rejection::MissingTimestamp));
+        assert_eq!(limit.point(7, Some(42)), Ok(42));
+    }
+}
@@ -1,78 +1,824 @@
-use clap::Parser;
-use csv::{ReaderBuilder, Writer};
-use std::io;
+use clap::{Args, Parser, Subcommand};
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-mod transaction;
-use transaction::{Action, ActionKind, Transaction, TransactionKind};
+use kekeke::{
+    ClientId, Transaction, audit,
+    budget::{self, ErrorBudget},
+    config::{self, Config},
+    diff,
+    input::{self, InputFormat, Transactions},
+    limits,
+    output::{
+        self, AccountFilter, AccountView, DecimalFormat, ExtendedOutputRow, OutputFormat,
+        OutputRow, Precision, Rounding, SortColumn, TrailingZeros, TrialBalanceRow,
+    },
+    payments::Payments,
+    reconcile, rules, shard, state,
+    statement::{Statement, StatementRow},
+    trace::Tracer,
+    transaction::{AmountError, AmountPolicy, ScalePolicy},
+    validate::{IssueKind, Validator},
+};
 
-mod payments;
-use payments::Payments;
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, arg_required_else_help = true)]
+struct Options {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-mod account;
-use account::Account;
+    /// Without a subcommand the final balances are printed
+    #[command(flatten)]
+    engine: Option<EngineOptions>,
 
-mod output;
-use output::OutputRow;
+    #[command(flatten)]
+    view: ViewOptions,
+}
 
-#[derive(Parser)]
-struct Options {
+#[derive(Subcommand)]
+enum Command {
+    /// Print debit and credit totals of every ledger account instead of the balances
+    TrialBalance(EngineOptions),
+    /// Check the engine invariants after every transaction and report the violations
+    Audit(EngineOptions),
+    /// Print every transaction of a single client along with its effect on the balances
+    Statement(StatementOptions),
+    /// Compare two outputs or saved states, exits with 1 if they differ and with 2 on errors
+    Diff(DiffOptions),
+    /// Compare the final balances against the expected ones and report the mismatches
+    Reconcile(ReconcileOptions),
+    /// Check the input for malformed rows and transactions the engine would turn down,
+    /// without printing the balances
+    Validate(EngineOptions),
+}
+
+#[derive(Args)]
+struct ReconcileOptions {
+    /// Csv file with the expected balances, in the same format as the output
+    #[arg(long)]
+    expected: std::path::PathBuf,
+
+    /// Largest difference between two balances that still counts as a match
+    #[arg(long, default_value = "0")]
+    tolerance: rust_decimal::Decimal,
+
+    #[command(flatten)]
+    engine: EngineOptions,
+}
+
+#[derive(Args)]
+struct DiffOptions {
+    /// Csv output or saved state of one run
+    left: std::path::PathBuf,
+
+    /// Csv output or saved state of the other run
+    right: std::path::PathBuf,
+
+    /// Format of the output
+    #[arg(long, value_enum, default_value_t)]
+    format: OutputFormat,
+}
+
+#[derive(Args)]
+struct StatementOptions {
+    /// The client to print the statement of
+    #[arg(long)]
+    client: ClientId,
+
+    #[command(flatten)]
+    engine: EngineOptions,
+}
+
+#[derive(Args)]
+struct EngineOptions {
+    /// Csv or ndjson file with the transactions, optionally gzip or zstd compressed,
+    /// or - to read the standard input
     path: std::path::PathBuf,
+
+    /// Format of the input, guessed by the file extension if not set
+    #[arg(long, value_enum)]
+    input_format: Option<InputFormat>,
+
+    /// Csv file with per client withdrawal velocity limits
+    #[arg(long)]
+    limits: Option<std::path::PathBuf>,
+
+    /// Run every transaction through the built in fraud rules first
+    #[arg(long)]
+    fraud_rules: bool,
+
+    /// Check the engine invariants after every transaction, honoured by debug builds only
+    #[arg(long)]
+    check_invariants: bool,
+
+    /// Toml file with the amount and output settings, overridden by the options below
+    #[arg(long)]
+    config: Option<std::path::PathBuf>,
+
+    /// Most decimal places an amount may have, unlimited if not set
+    #[arg(long)]
+    amount_scale: Option<u32>,
+
+    /// What to do with amounts that have more decimal places than the scale allows [default: reject]
+    #[arg(long, value_enum)]
+    amount_rounding: Option<ScalePolicy>,
+
+    /// Largest amount a single transaction may carry, unlimited if not set
+    #[arg(long)]
+    max_amount: Option<rust_decimal::Decimal>,
+
+    /// Decimal places of the printed balances, or full to print them unrounded [default: 4]
+    #[arg(long)]
+    output_precision: Option<Precision>,
+
+    /// How the printed balances are rounded to the precision [default: half-even]
+    #[arg(long, value_enum)]
+    output_rounding: Option<Rounding>,
+
+    /// Whether the printed balances are padded with zeros up to the precision [default: keep]
+    #[arg(long, value_enum)]
+    trailing_zeros: Option<TrailingZeros>,
+
+    /// Format of the output
+    #[arg(long, value_enum, default_value_t)]
+    format: OutputFormat,
+
+    /// Write what every input row did to the engine to the given file, as ndjson
+    #[arg(long)]
+    trace: Option<std::path::PathBuf>,
+
+    /// Save the final state of the engine to the given file, for a later diff
+    #[arg(long)]
+    save_state: Option<std::path::PathBuf>,
+
+    /// Abort on the first row that can not be parsed or is rejected
+    #[arg(long, conflicts_with = "max_errors")]
+    strict: bool,
+
+    /// Abort as soon as more than this many rows could not be parsed or were rejected
+    #[arg(long)]
+    max_errors: Option<u64>,
+
+    /// Abort if more than this fraction of the rows could not be parsed or were rejected,
+    /// checked as the rows are read once there are 1000 of them, and at the end
+    #[arg(long, value_parser = budget::parse_rate)]
+    max_error_rate: Option<f64>,
+
+    /// Process the clients on this many threads, the results are the same as on one
+    #[arg(
+        long,
+        default_value_t = 1,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        conflicts_with_all = ["trace", "strict", "max_errors", "fraud_rules"]
+    )]
+    threads: usize,
+}
+
+// Which of the final balances get printed, and in what order
+#[derive(Args)]
+struct ViewOptions {
+    /// Print locked accounts only
+    #[arg(long)]
+    locked_only: bool,
+
+    /// Print accounts with a negative total only
+    #[arg(long)]
+    negative_total: bool,
+
+    /// Print accounts with some funds held only
+    #[arg(long)]
+    nonzero_held: bool,
+
+    /// Print the given clients only, comma separated
+    #[arg(long, value_delimiter = ',')]
+    clients: Vec<ClientId>,
+
+    /// Sort the accounts by the given column instead of the client id
+    #[arg(long, value_enum)]
+    sort: Option<SortColumn>,
+
+    /// Sort in descending order
+    #[arg(long, requires = "sort")]
+    desc: bool,
+
+    /// Print at most this many accounts, after sorting
+    #[arg(long)]
+    limit: Option<usize>,
+
+    /// Print the activity counters of the accounts along with the balances
+    #[arg(long)]
+    extended: bool,
+}
+
+impl ViewOptions {
+    fn to_view(&self) -> AccountView {
+        AccountView {
+            filter: AccountFilter {
+                locked_only: self.locked_only,
+                negative_total: self.negative_total,
+                nonzero_held: self.nonzero_held,
+                clients: self.clients.clone(),
+            },
+            sort: self.sort,
+            descending: self.desc,
+            limit: self.limit,
+        }
+    }
+}
+
+// The config file, if any, with the command line options laid over it
+fn load_config(options: &EngineOptions) -> anyhow::Result<Config> {
+    let mut config = match &options.config {
+        Some(config_path) => config::load_config(
+            config_path
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("The path to the config file is invalid"))?,
+        )?,
+        None => Config::default(),
+    };
+
+    let amounts = &mut config.amounts;
+    amounts.scale = options.amount_scale.or(amounts.scale);
+    amounts.scale_policy = options.amount_rounding.unwrap_or(amounts.scale_policy);
+    amounts.max_amount = options.max_amount.or(amounts.max_amount);
+
+    let output = &mut config.output;
+    output.precision = options.output_precision.unwrap_or(output.precision);
+    output.rounding = options.output_rounding.unwrap_or(output.rounding);
+    output.trailing_zeros = options.trailing_zeros.unwrap_or(output.trailing_zeros);
+
+    Ok(config)
 }
 
-fn process_csv(payments: &mut Payments, input_path: &str) -> anyhow::Result<()> {
-    for result in ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_path(input_path)?
-        .deserialize::<Transaction>()
-    {
+// Everything about a parsed transaction that doesn't depend on the rest of the input,
+// which is all a shard gets to see
+fn apply_transaction(
+    payments: &mut Payments,
+    transaction: &Transaction,
+    amount_check: Result<(), AmountError>,
+) -> Result<(), (IssueKind, String)> {
+    let result = match amount_check {
+        Ok(()) => payments
+            .process_transaction(transaction)
+            .map_err(|rejection| (IssueKind::from(rejection), rejection.to_string())),
+        Err(amount_error) => {
+            payments.reject(transaction, &amount_error);
+            Err((IssueKind::InvalidAmount, amount_error.to_string()))
+        }
+    };
+    for alert in payments.alerts.drain(..) {
+        eprintln!(
+            "Alert: [{}] transaction {} of client {}: {}",
+            alert.rule, alert.tid, alert.cid, alert.message
+        )
+    }
+    result
+}
+
+fn warn_rejected(transaction: &Transaction, rejection: &str) {
+    eprintln!(
+        "Warning: Rejected transaction {} of client {}: {rejection}",
+        transaction.tid, transaction.cid
+    )
+}
+
+fn process_input(
+    payments: &mut Payments,
+    policy: &AmountPolicy,
+    transactions: Transactions,
+    mut tracer: Option<&mut Tracer>,
+    mut validator: Option<&mut Validator>,
+    budget: &mut ErrorBudget,
+) -> anyhow::Result<()> {
+    for (index, (line, result)) in transactions.enumerate() {
+        let row = index as u64 + 1;
         match result {
-            Ok(transaction) => payments.process_transaction(&transaction),
+            Ok(mut transaction) => {
+                let before = tracer
+                    .as_ref()
+                    .map(|tracer| tracer.snapshot(payments, &transaction));
+                if let Some(validator) = validator.as_deref_mut() {
+                    validator.check(line, &transaction);
+                }
+                let amount_check = transaction.apply_amount_policy(policy);
+                let result = apply_transaction(payments, &transaction, amount_check);
+                // A validation reports the rejections on its own
+                match (&result, validator.as_deref_mut()) {
+                    (Err((kind, rejection)), Some(validator)) => {
+                        validator.record_rejection(line, &transaction, *kind, rejection)
+                    }
+                    (Err((_, rejection)), None) => warn_rejected(&transaction, rejection),
+                    (Ok(()), _) => {}
+                }
+                if let (Some(tracer), Some(before)) = (tracer.as_deref_mut(), before) {
+                    let rejection = result
+                        .as_ref()
+                        .err()
+                        .map(|(_, r)| r as &dyn std::fmt::Display);
+                    tracer.record(line, payments, &transaction, before, rejection)?;
+                }
+                // The rate may also go over on a row that is fine, once enough rows were seen
+                if let Err(exceeded) = budget.record(result.is_err()) {
+                    match &result {
+                        Err((_, rejection)) => {
+                            anyhow::bail!("Aborted at row {row}, {exceeded}: {rejection}")
+                        }
+                        Ok(()) => anyhow::bail!("Aborted at row {row}, {exceeded}"),
+                    }
+                }
+            }
             // According to the spec we are not suppose to fatal the process should we encounter a
             // faulty transaction, so, we'll just complain and proceed
             Err(deserialization_error) => {
-                eprintln!("Warning: Failed to parse transaction: {deserialization_error}")
+                match validator.as_deref_mut() {
+                    Some(validator) => validator.record_malformed(line, &deserialization_error),
+                    None => {
+                        eprintln!("Warning: Failed to parse transaction: {deserialization_error}")
+                    }
+                }
+                if let Some(tracer) = tracer.as_deref_mut() {
+                    tracer.record_unparsed(line, &deserialization_error)?;
+                }
+                if let Err(exceeded) = budget.record(true) {
+                    anyhow::bail!("Aborted at row {row}, {exceeded}: {deserialization_error}");
+                }
             }
         }
     }
+    if let Some(tracer) = tracer {
+        tracer.flush()?;
+    }
+    if let Err(exceeded) = budget.finish() {
+        anyhow::bail!("Aborted, {exceeded}");
+    }
 
     Ok(())
 }
 
-fn main() -> anyhow::Result<()> {
-    let options = Options::parse();
-    let mut payments = Payments::default();
+// A transaction on its way to a shard, along with what only the reader knows about it
+struct Job {
+    transaction: Transaction,
+    amount_check: Result<(), AmountError>,
+    // The number of transactions that went through the engine before this one, no matter
+    // the shard, row based velocity limits count in those
+    engine_row: u64,
+}
+
+// Transactions go to the shards in batches, a channel send per transaction costs too much
+const SHARD_BATCH: usize = 1024;
+// Batches a shard may fall behind the reader, before the reader waits for it
+const SHARD_QUEUE: usize = 16;
+
+// Reads the input on the current thread and fans the transactions out to the shards,
+// one thread each. Every client has a shard of its own, which sees its transactions in
+// the order of the input, so once merged the shards are the same as a single engine
+fn process_sharded(
+    shards: Vec<Payments>,
+    policy: &AmountPolicy,
+    transactions: Transactions,
+    budget: &mut ErrorBudget,
+) -> anyhow::Result<Payments> {
+    let count = shards.len();
+    // The shards count their rejections as they go, for the error rate to be checked while
+    // the input is still read, if a little behind it
+    let rejected = AtomicU64::new(0);
+    let shards = std::thread::scope(|scope| -> anyhow::Result<_> {
+        let (senders, workers): (Vec<_>, Vec<_>) = shards
+            .into_iter()
+            .map(|mut payments| {
+                let (sender, receiver) = std::sync::mpsc::sync_channel::<Vec<Job>>(SHARD_QUEUE);
+                let rejected = &rejected;
+                let worker = scope.spawn(move || {
+                    for job in receiver.into_iter().flatten() {
+                        payments.rows_processed = job.engine_row;
+                        let result =
+                            apply_transaction(&mut payments, &job.transaction, job.amount_check);
+                        if let Err((_, rejection)) = result {
+                            warn_rejected(&job.transaction, &rejection);
+                            rejected.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                    payments
+                });
+                (sender, worker)
+            })
+            .unzip();
+
+        let mut batches: Vec<Vec<Job>> = (0..count)
+            .map(|_| Vec::with_capacity(SHARD_BATCH))
+            .collect();
+        let mut engine_row = 0;
+        // A single engine lets the last transaction to use a tid have it, whatever the client,
+        // while every shard would keep its own. Those inputs can't come out the same
+        let mut owners = HashMap::new();
+        for (index, (line, result)) in transactions.enumerate() {
+            let row = index as u64 + 1;
+            match result {
+                Ok(mut transaction) => {
+                    if transaction.kind.creates_tid()
+                        && let owner = *owners.entry(transaction.tid).or_insert(transaction.cid)
+                        && owner != transaction.cid
+                    {
+                        anyhow::bail!(
+                            "Transaction {} of client {} on line {line} reuses the tid of client \
+                             {owner}, which can not be processed on more than one thread",
+                            transaction.tid,
+                            transaction.cid
+                        );
+                    }
+                    let amount_check = transaction.apply_amount_policy(policy);
+                    let shard = shard::shard_of(transaction.cid, count);
+                    let job = Job {
+                        engine_row,
+                        amount_check,
+                        transaction,
+                    };
+                    if job.amount_check.is_ok() {
+                        engine_row += 1;
+                    }
+                    batches[shard].push(job);
+                    if batches[shard].len() == SHARD_BATCH {
+                        let batch =
+                            std::mem::replace(&mut batches[shard], Vec::with_capacity(SHARD_BATCH));
+                        senders[shard]
+                            .send(batch)
+                            .map_err(|_| anyhow::anyhow!("Shard {shard} has stopped"))?;
+                    }
+                    budget.record_errors(rejected.swap(0, Ordering::Relaxed));
+                    if let Err(exceeded) = budget.record(false) {
+                        anyhow::bail!("Aborted at row {row}, {exceeded}");
+                    }
+                }
+                Err(deserialization_error) => {
+                    eprintln!("Warning: Failed to parse transaction: {deserialization_error}");
+                    budget.record_errors(rejected.swap(0, Ordering::Relaxed));
+                    if let Err(exceeded) = budget.record(true) {
+                        anyhow::bail!("Aborted at row {row}, {exceeded}: {deserialization_error}");
+                    }
+                }
+            }
+        }
+        for (shard, (sender, batch)) in senders.into_iter().zip(batches).enumerate() {
+            sender
+                .send(batch)
+                .map_err(|_| anyhow::anyhow!("Shard {shard} has stopped"))?;
+        }
+
+        let mut shards = Vec::with_capacity(count);
+        for worker in workers {
+            shards.push(
+                worker
+                    .join()
+                    .map_err(|_| anyhow::anyhow!("A shard has panicked"))?,
+            );
+        }
+        Ok(shards)
+    })?;
+
+    budget.record_errors(rejected.into_inner());
+    if let Err(exceeded) = budget.finish() {
+        anyhow::bail!("Aborted, {exceeded}");
+    }
+    Ok(shard::merge(shards))
+}
+
+// Builds an engine as the options say, one for each shard
+fn build_engine(
+    options: &EngineOptions,
+    audit: bool,
+    statement_client: Option<ClientId>,
+) -> anyhow::Result<Payments> {
+    let mut payments = Payments {
+        statement: statement_client.map(Statement::new),
+        ..Default::default()
+    };
+    if let Some(limits_path) = &options.limits {
+        payments.limits = limits::load_limits(
+            limits_path
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("The path to the limits file is invalid"))?,
+        )?;
+    }
+    if options.fraud_rules {
+        payments.rules = rules::default_rules();
+    }
+    if audit || (options.check_invariants && cfg!(debug_assertions)) {
+        payments.auditor = Some(audit::Auditor::default());
+    }
+    Ok(payments)
+}
+
+// Builds the engine as the options say and feeds the whole input through it.
+// An audit checks the invariants no matter what the build is
+fn run_engine(
+    options: &EngineOptions,
+    config: &Config,
+    audit: bool,
+    statement_client: Option<ClientId>,
+    validator: Option<&mut Validator>,
+) -> anyhow::Result<Payments> {
+    if options.check_invariants && !cfg!(debug_assertions) && !audit {
+        eprintln!("Warning: --check-invariants is ignored by release builds, try the audit command")
+    }
 
     // Processing all the transactions from the input file,
     // mutating the state of the payments instance
-    process_csv(
+    let format = options
+        .input_format
+        .unwrap_or_else(|| InputFormat::from_path(&options.path));
+    let mut budget = ErrorBudget::new(
+        if options.strict {
+            Some(0)
+        } else {
+            options.max_errors
+        },
+        options.max_error_rate,
+    );
+    let transactions = input::read_transactions(&options.path, format, &config.csv)?;
+
+    if options.threads > 1 {
+        // A validation has to see the rows in order, which no shard does
+        if validator.is_some() {
+            anyhow::bail!("Validation can not run on more than one thread");
+        }
+        // Nor does any shard know the transactions of the others, so a statement would give
+        // other reasons for turning down references to them than a single engine does
+        if statement_client.is_some() {
+            anyhow::bail!("A statement can not be made on more than one thread");
+        }
+        let shards = (0..options.threads)
+            .map(|_| build_engine(options, audit, statement_client))
+            .collect::<anyhow::Result<_>>()?;
+        let payments = process_sharded(shards, &config.amounts, transactions, &mut budget)?;
+        if let Some(state_path) = &options.save_state {
+            state::save_state(&payments, state_path)?;
+        }
+        return Ok(payments);
+    }
+
+    let mut payments = build_engine(options, audit, statement_client)?;
+    let mut tracer = match &options.trace {
+        Some(trace_path) => Some(Tracer::new(Box::new(io::BufWriter::new(
+            std::fs::File::create(trace_path)?,
+        )))),
+        None => None,
+    };
+    process_input(
         &mut payments,
-        options
-            .path
-            .to_str()
-            .ok_or_else(|| anyhow::anyhow!("The path path to the input is invalid"))?,
+        &config.amounts,
+        transactions,
+        tracer.as_mut(),
+        validator,
+        &mut budget,
     )?;
+    if let Some(state_path) = &options.save_state {
+        state::save_state(&payments, state_path)?;
+    }
+
+    Ok(payments)
+}
+
+fn print_balances(
+    payments: &Payments,
+    view: &AccountView,
+    extended: bool,
+    decimal_format: &DecimalFormat,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    for violation in &payments.violations {
+        eprintln!(
+            "Warning: Transaction {} broke the {} invariant of client {}: expected {}, got {}",
+            violation.tid.unwrap_or_default(),
+            violation.invariant,
+            violation.cid,
+            violation.expected,
+            violation.actual
+        )
+    }
 
     // Filtering out only the accounts that actually participated
     // and building the output stream from them
-    let output_stream = payments
+    let active_accounts = payments
         .accounts
         .iter()
-        .enumerate()
-        .filter(|(_, account)| account.has_activity)
-        .map(|(client_id, account)| OutputRow {
-            client: client_id as u16,
-            available: account.total - account.held,
-            held: account.held,
-            total: account.total,
-            locked: account.is_locked,
-        });
+        .filter(|(_, account)| account.has_activity);
+    let selected = view.select(active_accounts).into_iter();
 
     // Actually writing the output to stdout
-    let mut writer = Writer::from_writer(io::stdout());
-    for account in output_stream {
-        writer.serialize(account)?;
+    if extended {
+        let output_stream = selected.map(|(client_id, account)| {
+            let stats = &payments.stats[client_id];
+            ExtendedOutputRow::new(client_id, account, stats, decimal_format)
+        });
+        output::write_rows(io::stdout().lock(), format, output_stream)
+    } else {
+        let output_stream =
+            selected.map(|(client_id, account)| OutputRow::new(client_id, account, decimal_format));
+        output::write_rows(io::stdout().lock(), format, output_stream)
+    }
+}
+
+fn print_trial_balance(payments: &Payments, format: OutputFormat) -> anyhow::Result<()> {
+    let totals = payments
+        .ledger
+        .totals()
+        .ok_or_else(|| anyhow::anyhow!("The ledger totals are too large to add up"))?;
+    let rows = payments
+        .ledger
+        .trial_balance()
+        .map(|(account, totals)| (account.to_string(), totals))
+        .chain([("total".to_string(), totals)])
+        .map(|(account, totals)| TrialBalanceRow {
+            account,
+            debits: totals.debits,
+            credits: totals.credits,
+            balance: totals.balance,
+        });
+    output::write_rows(io::stdout().lock(), format, rows)?;
+
+    // Should never happen, but if it does, somebody has to know
+    if totals.debits != totals.credits {
+        anyhow::bail!("The ledger does not balance: {totals:?}");
+    }
+
+    Ok(())
+}
+
+fn print_statement(
+    payments: &Payments,
+    decimal_format: &DecimalFormat,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let Some(statement) = &payments.statement else {
+        anyhow::bail!("The statement was not recorded");
+    };
+    let rows = statement
+        .lines
+        .iter()
+        .map(|line| StatementRow::new(line, decimal_format));
+    output::write_rows(io::stdout().lock(), format, rows)
+}
+
+// Compares the balances as they would have been printed, so the output settings apply
+fn print_reconciliation(
+    payments: &Payments,
+    options: &ReconcileOptions,
+    decimal_format: &DecimalFormat,
+) -> anyhow::Result<()> {
+    let expected = state::load_state(&options.expected)?;
+    let mut actual = state::SavedState::from(payments);
+    for account in actual.accounts.values_mut() {
+        account.available = decimal_format.round(account.available);
+        account.held = decimal_format.round(account.held);
+        account.total = decimal_format.round(account.total);
+    }
+
+    let mismatches = reconcile::reconcile(&expected, &actual, options.tolerance);
+    output::write_rows(io::stdout().lock(), options.engine.format, &mismatches)?;
+
+    let mismatched_clients: BTreeSet<_> = mismatches.iter().map(|m| m.client).collect();
+    let clients: BTreeSet<_> = expected
+        .accounts
+        .keys()
+        .chain(actual.accounts.keys())
+        .collect();
+    eprintln!(
+        "Reconciled {} clients: {} matched, {} mismatched",
+        clients.len(),
+        clients.len() - mismatched_clients.len(),
+        mismatched_clients.len()
+    );
+    if !mismatches.is_empty() {
+        anyhow::bail!("Found {} mismatches", mismatches.len());
+    }
+
+    Ok(())
+}
+
+fn print_validation(validator: &Validator, format: OutputFormat) -> anyhow::Result<()> {
+    output::write_rows(io::stdout().lock(), format, &validator.issues)?;
+
+    let faulty_rows: BTreeSet<_> = validator.issues.iter().map(|issue| issue.line).collect();
+    eprintln!(
+        "Validated {} rows: {} fine, {} with issues",
+        validator.rows,
+        validator.rows - faulty_rows.len() as u64,
+        faulty_rows.len()
+    );
+    if !validator.issues.is_empty() {
+        anyhow::bail!("Found {} issues", validator.issues.len());
+    }
+
+    Ok(())
+}
+
+fn print_audit(payments: &mut Payments, format: OutputFormat) -> anyhow::Result<()> {
+    // Whatever has slipped through the per transaction checks gets caught here
+    let mut violations = std::mem::take(&mut payments.violations);
+    let leftovers: Vec<_> = audit::audit(payments)
+        .into_iter()
+        .filter(|violation| !violations.iter().any(|seen| seen.cid == violation.cid))
+        .collect();
+    violations.extend(leftovers);
+
+    output::write_rows(io::stdout().lock(), format, &violations)?;
+
+    if !violations.is_empty() {
+        anyhow::bail!("Found {} invariant violations", violations.len());
     }
-    writer.flush()?;
 
     Ok(())
 }
+
+// Whether the two sides differ, it's up to the caller to make an exit code out of it
+fn print_diff(options: &DiffOptions) -> anyhow::Result<bool> {
+    let differences = diff::diff(
+        &state::load_state(&options.left)?,
+        &state::load_state(&options.right)?,
+    );
+    output::write_rows(io::stdout().lock(), options.format, &differences)?;
+    Ok(!differences.is_empty())
+}
+
+fn main() -> anyhow::Result<()> {
+    match Options::parse() {
+        Options {
+            command: Some(Command::Diff(options)),
+            ..
+        } => match print_diff(&options) {
+            Ok(is_different) => std::process::exit(is_different as i32),
+            Err(error) => {
+                eprintln!("Error: {error:?}");
+                std::process::exit(2)
+            }
+        },
+        Options {
+            command: Some(Command::TrialBalance(engine)),
+            ..
+        } => print_trial_balance(
+            &run_engine(&engine, &load_config(&engine)?, false, None, None)?,
+            engine.format,
+        ),
+        Options {
+            command: Some(Command::Audit(engine)),
+            ..
+        } => print_audit(
+            &mut run_engine(&engine, &load_config(&engine)?, true, None, None)?,
+            engine.format,
+        ),
+        Options {
+            command: Some(Command::Statement(StatementOptions { client, engine })),
+            ..
+        } => {
+            let config = load_config(&engine)?;
+            print_statement(
+                &run_engine(&engine, &config, false, Some(client), None)?,
+                &config.output,
+                engine.format,
+            )
+        }
+        Options {
+            command: Some(Command::Validate(engine)),
+            ..
+        } => {
+            let mut validator = Validator::default();
+            run_engine(
+                &engine,
+                &load_config(&engine)?,
+                false,
+                None,
+                Some(&mut validator),
+            )?;
+            print_validation(&validator, engine.format)
+        }
+        Options {
+            command: Some(Command::Reconcile(options)),
+            ..
+        } => {
+            let config = load_config(&options.engine)?;
+            print_reconciliation(
+                &run_engine(&options.engine, &config, false, None, None)?,
+                &options,
+                &config.output,
+            )
+        }
+        Options {
+            engine: Some(engine),
+            view,
+            ..
+        } => {
+            let config = load_config(&engine)?;
+            print_balances(
+                &run_engine(&engine, &config, false, None, None)?,
+                &view.to_view(),
+                view.extended,
+                &config.output,
+                engine.format,
+            )
+        }
+        Options { .. } => anyhow::bail!("Missing path to the input"),
+    }
+}
@@ -1,26 +1,679 @@
-use rust_decimal::Decimal;
-use serde::{Serialize, Serializer};
+use std::{io::Write, str::FromStr};
 
-// Though it's not strickly required, lets keep our output nice and tidy
-fn serialize_decimal_4dp<S>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    serializer.serialize_str(&format!("{:.4}", value.round_dp(4)))
+use csv::{ReaderBuilder, Writer};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ClientId, TransactionId,
+    account::{Account, ClientStats},
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rounding {
+    // Banker's rounding, half goes to the nearest even digit
+    #[default]
+    HalfEven,
+    // Half goes away from zero, as taught at school
+    HalfUp,
+    // Whatever does not fit is cut off
+    Truncate,
+}
+
+impl From<Rounding> for RoundingStrategy {
+    fn from(rounding: Rounding) -> Self {
+        match rounding {
+            Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::Truncate => RoundingStrategy::ToZero,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "PrecisionSetting")]
+pub enum Precision {
+    // Nothing gets rounded, the value goes out the way it is kept
+    Full,
+    Places(u32),
+}
+
+// Either a number of decimal places or "full", in the config file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PrecisionSetting {
+    Places(u32),
+    Named(String),
+}
+
+impl TryFrom<PrecisionSetting> for Precision {
+    type Error = String;
+
+    fn try_from(setting: PrecisionSetting) -> Result<Self, Self::Error> {
+        match setting {
+            PrecisionSetting::Places(places) => Ok(Precision::Places(places)),
+            PrecisionSetting::Named(name) => name.parse(),
+        }
+    }
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(Precision::Full),
+            _ => s
+                .parse()
+                .map(Precision::Places)
+                .map_err(|_| format!("precision must be a number of places or full, not {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingZeros {
+    // Pads the value up to the precision, 1.5 goes out as 1.5000 at 4 places
+    #[default]
+    Keep,
+    // Goes out as short as possible, 1.5000 goes out as 1.5
+    Strip,
+}
+
+// How the balances are written out. By default that's 4 places, banker's rounding and
+// padded with zeros, which is nice and tidy though not strictly required
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DecimalFormat {
+    pub precision: Precision,
+    pub rounding: Rounding,
+    pub trailing_zeros: TrailingZeros,
+}
+
+impl Default for DecimalFormat {
+    fn default() -> Self {
+        DecimalFormat {
+            precision: Precision::Places(4),
+            rounding: Rounding::default(),
+            trailing_zeros: TrailingZeros::default(),
+        }
+    }
+}
+
+impl DecimalFormat {
+    // The value as it is about to be printed, rounded to the precision
+    pub fn round(&self, value: Decimal) -> Decimal {
+        match self.precision {
+            Precision::Full => value,
+            Precision::Places(places) => value.round_dp_with_strategy(places, self.rounding.into()),
+        }
+    }
+
+    pub fn format(&self, value: Decimal) -> String {
+        let value = self.round(value);
+        match (self.trailing_zeros, self.precision) {
+            (TrailingZeros::Strip, _) => value.normalize().to_string(),
+            // Rounded, the value has no more places than asked for, the rest are padded on by
+            // hand. Decimal can not pad a value to more digits than it holds, large ones panic
+            (TrailingZeros::Keep, Precision::Places(places)) => {
+                let mut text = value.to_string();
+                let missing = places.saturating_sub(value.scale());
+                if missing > 0 && value.scale() == 0 {
+                    text.push('.');
+                }
+                text.extend(std::iter::repeat_n('0', missing as usize));
+                text
+            }
+            (TrailingZeros::Keep, Precision::Full) => value.to_string(),
+        }
+    }
 }
 
+// The balances are formatted up front, so that every one of them goes out the same way
 #[derive(Debug, Serialize)]
 pub struct OutputRow {
-    pub client: u16,
-
-    #[serde(serialize_with = "serialize_decimal_4dp")]
-    pub available: Decimal,
+    pub client: ClientId,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: bool,
+}
 
-    #[serde(serialize_with = "serialize_decimal_4dp")]
-    pub held: Decimal,
+impl Columns for OutputRow {
+    const COLUMNS: &'static [&'static str] = &["client", "available", "held", "total", "locked"];
+}
 
-    #[serde(serialize_with = "serialize_decimal_4dp")]
-    pub total: Decimal,
+impl OutputRow {
+    pub fn new(client: ClientId, account: &Account, format: &DecimalFormat) -> Self {
+        OutputRow {
+            client,
+            available: format.format(account.get_available()),
+            held: format.format(account.held),
+            total: format.format(account.total),
+            locked: account.is_locked,
+        }
+    }
+}
 
+// The balances along with the activity counters of the client, for a closer look.
+// Csv can't flatten, hence the columns of OutputRow all over again
+#[derive(Debug, Serialize)]
+pub struct ExtendedOutputRow {
+    pub client: ClientId,
+    pub available: String,
+    pub held: String,
+    pub total: String,
     pub locked: bool,
+    pub deposits: u64,
+    pub withdrawals: u64,
+    pub deposited: String,
+    pub withdrawn: String,
+    pub open_disputes: u64,
+    pub disputed: String,
+    pub chargebacks: u64,
+    pub rejected: u64,
+    pub last_tx: Option<TransactionId>,
+}
+
+impl Columns for ExtendedOutputRow {
+    const COLUMNS: &'static [&'static str] = &[
+        "client",
+        "available",
+        "held",
+        "total",
+        "locked",
+        "deposits",
+        "withdrawals",
+        "deposited",
+        "withdrawn",
+        "open_disputes",
+        "disputed",
+        "chargebacks",
+        "rejected",
+        "last_tx",
+    ];
+}
+
+impl ExtendedOutputRow {
+    pub fn new(
+        client: ClientId,
+        account: &Account,
+        stats: &ClientStats,
+        format: &DecimalFormat,
+    ) -> Self {
+        let OutputRow {
+            client,
+            available,
+            held,
+            total,
+            locked,
+        } = OutputRow::new(client, account, format);
+        ExtendedOutputRow {
+            client,
+            available,
+            held,
+            total,
+            locked,
+            deposits: stats.deposits,
+            withdrawals: stats.withdrawals,
+            deposited: format.format(stats.deposited),
+            withdrawn: format.format(stats.withdrawn),
+            open_disputes: stats.open_disputes,
+            disputed: format.format(stats.disputed),
+            chargebacks: stats.chargebacks,
+            rejected: stats.rejected,
+            last_tx: stats.last_tid,
+        }
+    }
+}
+
+// Narrows the accounts down to those worth a look, all of the set conditions must hold
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AccountFilter {
+    pub locked_only: bool,
+    pub negative_total: bool,
+    pub nonzero_held: bool,
+    // Any client goes if empty
+    pub clients: Vec<ClientId>,
+}
+
+impl AccountFilter {
+    pub fn matches(&self, client: ClientId, account: &Account) -> bool {
+        (!self.locked_only || account.is_locked)
+            && (!self.negative_total || account.total < Decimal::ZERO)
+            && (!self.nonzero_held || !account.held.is_zero())
+            && (self.clients.is_empty() || self.clients.contains(&client))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortColumn {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+}
+
+// Which accounts go out and in what order. By default that's every active one by client id
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AccountView {
+    pub filter: AccountFilter,
+    pub sort: Option<SortColumn>,
+    pub descending: bool,
+    pub limit: Option<usize>,
+}
+
+impl AccountView {
+    // Expects the accounts in client id order, which is what ties are left in.
+    // Sorting goes by the exact balances, not by the formatted ones
+    pub fn select<'a>(
+        &self,
+        accounts: impl Iterator<Item = (ClientId, &'a Account)>,
+    ) -> Vec<(ClientId, &'a Account)> {
+        let mut selected: Vec<_> = accounts
+            .filter(|(client, account)| self.filter.matches(*client, account))
+            .collect();
+
+        if let Some(column) = self.sort {
+            selected.sort_by(|(left_client, left), (right_client, right)| {
+                let ordering = match column {
+                    SortColumn::Client => left_client.cmp(right_client),
+                    SortColumn::Available => left.get_available().cmp(&right.get_available()),
+                    SortColumn::Held => left.held.cmp(&right.held),
+                    SortColumn::Total => left.total.cmp(&right.total),
+                    SortColumn::Locked => left.is_locked.cmp(&right.is_locked),
+                };
+                if self.descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            });
+        }
+        if let Some(limit) = self.limit {
+            selected.truncate(limit);
+        }
+
+        selected
+    }
+}
+
+// One line of the ledger trial balance, kept at full precision as it has to add up exactly
+#[derive(Debug, Serialize)]
+pub struct TrialBalanceRow {
+    pub account: String,
+    pub debits: Decimal,
+    pub credits: Decimal,
+    pub balance: Decimal,
+}
+
+impl Columns for TrialBalanceRow {
+    const COLUMNS: &'static [&'static str] = &["account", "debits", "credits", "balance"];
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    // A single json array of objects
+    Json,
+    // One json object per line
+    Ndjson,
+    // Aligned columns, for humans rather than machines
+    Table,
+}
+
+// The header of a row type, so that a table has one even with no rows to print. Has to
+// name the fields just like they are serialized
+pub trait Columns {
+    const COLUMNS: &'static [&'static str];
+}
+
+impl<T: Columns> Columns for &T {
+    const COLUMNS: &'static [&'static str] = T::COLUMNS;
+}
+
+// Writes the rows out in the given format. The values are serialized the same way by every
+// format, so the balances formatted up front go out exactly as they were formatted
+pub fn write_rows<T: Serialize + Columns>(
+    mut writer: impl Write,
+    format: OutputFormat,
+    rows: impl IntoIterator<Item = T>,
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Csv => {
+            let mut writer = Writer::from_writer(writer);
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+        }
+        OutputFormat::Json => {
+            let mut is_empty = true;
+            write!(writer, "[")?;
+            for row in rows {
+                write!(writer, "{}\n  ", if is_empty { "" } else { "," })?;
+                serde_json::to_writer(&mut writer, &row)?;
+                is_empty = false;
+            }
+            writeln!(writer, "{}]", if is_empty { "" } else { "\n" })?;
+        }
+        OutputFormat::Ndjson => {
+            for row in rows {
+                serde_json::to_writer(&mut writer, &row)?;
+                writeln!(writer)?;
+            }
+        }
+        OutputFormat::Table => write_table(writer, rows)?,
+    }
+
+    Ok(())
+}
+
+// Goes through csv first, which takes care of the headers and of turning every value into text
+fn write_table<T: Serialize + Columns>(
+    mut writer: impl Write,
+    rows: impl IntoIterator<Item = T>,
+) -> anyhow::Result<()> {
+    let mut csv_writer = Writer::from_writer(Vec::new());
+    for row in rows {
+        csv_writer.serialize(row)?;
+    }
+    let mut records = ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(csv_writer.into_inner()?.as_slice())
+        .into_records()
+        .collect::<Result<Vec<_>, _>>()?;
+    match records.first() {
+        Some(header) => debug_assert_eq!(header, T::COLUMNS),
+        None => records.push(T::COLUMNS.into()),
+    }
+
+    let mut widths = Vec::new();
+    for record in &records {
+        widths.resize(widths.len().max(record.len()), 0);
+        for (width, field) in widths.iter_mut().zip(record) {
+            *width = (*width).max(field.chars().count());
+        }
+    }
+
+    for (index, record) in records.iter().enumerate() {
+        let line = record
+            .iter()
+            .zip(&widths)
+            .map(|(field, &width)| {
+                // Numbers line up by their last digit, everything else by the first letter
+                if index > 0 && Decimal::from_str(field).is_ok() {
+                    format!("{field:>width$}")
+                } else {
+                    format!("{field:<width$}")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(writer, "{}", line.trim_end())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn default_format_is_4dp_bankers() {
+        let format = DecimalFormat::default();
+
+        assert_eq!(format.format(dec!(1.5)), "1.5000");
+        assert_eq!(format.format(dec!(1.00005)), "1.0000");
+        assert_eq!(format.format(dec!(1.00015)), "1.0002");
+        assert_eq!(format.format(dec!(-2)), "-2.0000");
+        assert_eq!(format.format(dec!(-0.00001)), "0.0000");
+        assert_eq!(
+            format.format(Decimal::MAX),
+            "79228162514264337593543950335.0000"
+        );
+    }
+
+    #[test]
+    fn half_up_at_2dp() {
+        let format = DecimalFormat {
+            precision: Precision::Places(2),
+            rounding: Rounding::HalfUp,
+            ..Default::default()
+        };
+
+        assert_eq!(format.format(dec!(1.005)), "1.01");
+        assert_eq!(format.format(dec!(-1.005)), "-1.01");
+        assert_eq!(format.format(dec!(3)), "3.00");
+        assert_eq!(format.format(dec!(3.1)), "3.10");
+        assert_eq!(
+            DecimalFormat {
+                precision: Precision::Places(0),
+                ..format
+            }
+            .format(dec!(2.5)),
+            "3"
+        );
+    }
+
+    #[test]
+    fn truncate_and_strip() {
+        let format = DecimalFormat {
+            precision: Precision::Places(2),
+            rounding: Rounding::Truncate,
+            trailing_zeros: TrailingZeros::Strip,
+        };
+
+        assert_eq!(format.format(dec!(1.109)), "1.1");
+        assert_eq!(format.format(dec!(7.000)), "7");
+    }
+
+    #[test]
+    fn full_precision() {
+        let format = DecimalFormat {
+            precision: Precision::Full,
+            ..Default::default()
+        };
+
+        assert_eq!(format.format(dec!(1.0000000001)), "1.0000000001");
+        assert_eq!(format.format(dec!(1.50)), "1.50");
+        assert_eq!(
+            DecimalFormat {
+                trailing_zeros: TrailingZeros::Strip,
+                ..format
+            }
+            .format(dec!(1.50)),
+            "1.5"
+        );
+    }
+
+    #[test]
+    fn precision_from_str() {
+        assert_eq!("full".parse(), Ok(Precision::Full));
+        assert_eq!("2".parse(), Ok(Precision::Places(2)));
+        assert!("two".parse::<Precision>().is_err());
+    }
+
+    fn rows() -> Vec<OutputRow> {
+        let format = DecimalFormat::default();
+        vec![
+            OutputRow::new(
+                1,
+                &Account {
+                    total: dec!(1.5),
+                    held: dec!(0),
+                    is_locked: false,
+                    has_activity: true,
+                },
+                &format,
+            ),
+            OutputRow::new(
+                12,
+                &Account {
+                    total: dec!(100),
+                    held: dec!(25.125),
+                    is_locked: true,
+                    has_activity: true,
+                },
+                &format,
+            ),
+        ]
+    }
+
+    fn write_to_string(format: OutputFormat, rows: Vec<OutputRow>) -> String {
+        let mut output = Vec::new();
+        write_rows(&mut output, format, rows).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn csv_output() {
+        assert_eq!(
+            write_to_string(OutputFormat::Csv, rows()),
+            "client,available,held,total,locked\n\
+             1,1.5000,0.0000,1.5000,false\n\
+             12,74.8750,25.1250,100.0000,true\n"
+        );
+    }
+
+    #[test]
+    fn json_output() {
+        assert_eq!(
+            write_to_string(OutputFormat::Json, rows()),
+            "[\n  \
+             {\"client\":1,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false},\n  \
+             {\"client\":12,\"available\":\"74.8750\",\"held\":\"25.1250\",\"total\":\"100.0000\",\"locked\":true}\n\
+             ]\n"
+        );
+        assert_eq!(write_to_string(OutputFormat::Json, vec![]), "[]\n");
+    }
+
+    #[test]
+    fn ndjson_output() {
+        let output = write_to_string(OutputFormat::Ndjson, rows());
+        let lines: Vec<_> = output.lines().collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "{\"client\":1,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false}"
+        );
+    }
+
+    #[test]
+    fn table_output() {
+        assert_eq!(
+            write_to_string(OutputFormat::Table, rows()),
+            "client  available  held     total     locked\n\
+             \x20    1     1.5000   0.0000    1.5000  false\n\
+             \x20   12    74.8750  25.1250  100.0000  true\n"
+        );
+        assert_eq!(
+            write_to_string(OutputFormat::Table, Vec::new()),
+            "client  available  held  total  locked\n"
+        );
+    }
+
+    fn account(total: Decimal, held: Decimal, is_locked: bool) -> Account {
+        Account {
+            total,
+            held,
+            is_locked,
+            has_activity: true,
+        }
+    }
+
+    fn select(view: &AccountView, accounts: &[Account]) -> Vec<ClientId> {
+        view.select(
+            accounts
+                .iter()
+                .enumerate()
+                .map(|(client, account)| (client as ClientId, account)),
+        )
+        .into_iter()
+        .map(|(client, _)| client)
+        .collect()
+    }
+
+    #[test]
+    fn filters_combine() {
+        let accounts = [
+            account(dec!(10), dec!(0), false),
+            account(dec!(-5), dec!(0), true),
+            account(dec!(3), dec!(3), true),
+            account(dec!(-1), dec!(2), false),
+        ];
+        let view = |filter| AccountView {
+            filter,
+            ..Default::default()
+        };
+
+        assert_eq!(select(&AccountView::default(), &accounts), vec![0, 1, 2, 3]);
+        assert_eq!(
+            select(
+                &view(AccountFilter {
+                    locked_only: true,
+                    ..Default::default()
+                }),
+                &accounts
+            ),
+            vec![1, 2]
+        );
+        assert_eq!(
+            select(
+                &view(AccountFilter {
+                    negative_total: true,
+                    nonzero_held: true,
+                    ..Default::default()
+                }),
+                &accounts
+            ),
+            vec![3]
+        );
+        assert_eq!(
+            select(
+                &view(AccountFilter {
+                    clients: vec![3, 0, 7],
+                    ..Default::default()
+                }),
+                &accounts
+            ),
+            vec![0, 3]
+        );
+    }
+
+    #[test]
+    fn top_by_held() {
+        let accounts = [
+            account(dec!(10), dec!(1), false),
+            account(dec!(10), dec!(5), false),
+            account(dec!(10), dec!(0.00001), false),
+            account(dec!(10), dec!(5), false),
+        ];
+        let view = AccountView {
+            sort: Some(SortColumn::Held),
+            descending: true,
+            limit: Some(3),
+            ..Default::default()
+        };
+
+        // Ties stay in client order
+        assert_eq!(select(&view, &accounts), vec![1, 3, 0]);
+        assert_eq!(
+            select(
+                &AccountView {
+                    sort: Some(SortColumn::Available),
+                    ..Default::default()
+                },
+                &accounts
+            ),
+            vec![1, 3, 0, 2]
+        );
+    }
 }
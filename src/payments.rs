@@ -1,32 +1,114 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+};
 
-use crate::{Account, Action, ActionKind, Transaction, TransactionKind, transaction::ActionStatus};
+use rust_decimal::Decimal;
 
+use crate::{
+    Account, Action, ActionKind, ClientId, Transaction, TransactionId, TransactionKind,
+    account::{ClientStats, Clients},
+    audit::{Auditor, Violation},
+    ledger::{Ledger, LedgerAccount, Posting},
+    limits::{VelocityLimit, WithdrawalWindow},
+    rejection::Rejection,
+    rules::{Alert, RECENT_ACTIONS_DEPTH, Rule, RuleContext, Verdict},
+    statement::Statement,
+    transaction::ActionStatus,
+};
+
+#[derive(Default)]
 pub struct Payments {
-    pub accounts: Vec<Account>,
-    pub actions: HashMap<u32, Action>,
+    pub accounts: Clients<Account>,
+    pub actions: HashMap<TransactionId, Action>,
+    // Optional per client withdrawal limits, along with the withdrawals they have seen so far
+    pub limits: HashMap<ClientId, VelocityLimit>,
+    pub withdrawal_windows: HashMap<ClientId, WithdrawalWindow>,
+    // Number of transactions fed into the engine so far, row based limits are counted in those
+    pub rows_processed: u64,
+    pub stats: Clients<ClientStats>,
+    // Fraud rules, consulted before every transaction, and whatever they had to say
+    pub rules: Vec<Box<dyn Rule>>,
+    pub alerts: Vec<Alert>,
+    pub recent_actions: HashMap<ClientId, VecDeque<TransactionId>>,
+    // Every balance change is posted here first, the accounts above just mirror it
+    pub ledger: Ledger,
+    // Optional invariant checks after every transaction, and whatever they have found
+    pub auditor: Option<Auditor>,
+    pub violations: Vec<Violation>,
+    // Optional history of a single client, for the statement
+    pub statement: Option<Statement>,
 }
 
-impl Default for Payments {
-    fn default() -> Self {
-        Payments {
-            accounts: vec![Account::default(); u16::MAX as usize + 1],
-            actions: Default::default(),
+impl Payments {
+    pub fn process_transaction(&mut self, transaction: &Transaction) -> Result<(), Rejection> {
+        let before = self.accounts[transaction.cid];
+        let mut auditor = self.auditor.take();
+        let previous = auditor
+            .as_ref()
+            .and_then(|_| self.actions.get(&transaction.tid).cloned());
+
+        let result = self.apply_transaction(transaction);
+        let rejection = result
+            .as_ref()
+            .err()
+            .map(|rejection| rejection as &dyn fmt::Display);
+        self.record_outcome(transaction, before, rejection);
+
+        if let Some(auditor) = &mut auditor {
+            let violations = auditor.check(self, transaction, previous.as_ref());
+            self.violations.extend(violations);
         }
+        self.auditor = auditor;
+
+        result
     }
-}
 
-impl Payments {
-    pub fn process_transaction(&mut self, transaction: &Transaction) {
+    // For transactions rejected before they even reach the engine, so that they are
+    // accounted for all the same
+    pub fn reject(&mut self, transaction: &Transaction, reason: &dyn fmt::Display) {
+        let before = self.accounts[transaction.cid];
+        self.record_outcome(transaction, before, Some(reason));
+    }
+
+    fn record_outcome(
+        &mut self,
+        transaction: &Transaction,
+        before: Account,
+        rejection: Option<&dyn fmt::Display>,
+    ) {
+        let stats = &mut self.stats[transaction.cid];
+        stats.last_tid = Some(transaction.tid);
+        if rejection.is_some() {
+            stats.rejected += 1;
+        }
+        if let Some(statement) = &mut self.statement {
+            statement.record(
+                transaction,
+                before,
+                self.accounts[transaction.cid],
+                rejection.map(|rejection| rejection.to_string()),
+            );
+        }
+    }
+
+    fn apply_transaction(&mut self, transaction: &Transaction) -> Result<(), Rejection> {
+        let row = self.rows_processed;
+        self.rows_processed += 1;
+
         let account = self.get_account_mut(transaction.cid);
         if account.is_locked {
-            return;
+            return Err(Rejection::AccountLocked);
         }
 
         // The stats console printer will pick up only the active users
         // so marking that one as active
         account.has_activity = true;
 
+        // Letting the fraud rules have their say before anything gets touched
+        self.apply_rules(transaction)?;
+        let account = self.get_account_mut(transaction.cid);
+
         // NOTE: we are about to store the transaction for later, and as a storage key
         // we are using the tid - transaction id.
         // We are not gonna sanitize it in any way here, according to the spec they
@@ -34,50 +116,247 @@ impl Payments {
         match transaction.kind {
             // Processing deposits
             TransactionKind::Deposit { amount } => {
-                account.total += amount;
-                self.actions.insert(
+                self.post(
+                    transaction.cid,
+                    LedgerAccount::House,
+                    LedgerAccount::Available(transaction.cid),
+                    amount,
+                )?;
+                let stats = &mut self.stats[transaction.cid];
+                stats.deposits += 1;
+                stats.deposited = stats.deposited.saturating_add(amount);
+                self.store_action(
                     transaction.tid,
                     Action {
                         cid: transaction.cid,
                         kind: ActionKind::Deposit { amount },
                         status: ActionStatus::Fresh,
+                        refunded: Decimal::ZERO,
                     },
                 );
             }
 
             // Processing withdrawals
             TransactionKind::Withdrawal { amount } => {
-                if account.get_available() >= amount {
-                    account.total -= amount;
-                    self.actions.insert(
-                        transaction.tid,
-                        Action {
-                            cid: transaction.cid,
-                            kind: ActionKind::Withdrawal { amount },
-                            status: ActionStatus::Fresh,
-                        },
-                    );
+                if account.get_available() < amount {
+                    return Err(Rejection::InsufficientFunds);
+                }
+                // The velocity limit goes last, so that only withdrawals which are
+                // actually about to happen are counted against it
+                let velocity_point = self.check_velocity(transaction, row, amount)?;
+
+                self.post(
+                    transaction.cid,
+                    LedgerAccount::Available(transaction.cid),
+                    LedgerAccount::House,
+                    amount,
+                )?;
+                self.record_withdrawal(transaction.cid, velocity_point, amount);
+                let stats = &mut self.stats[transaction.cid];
+                stats.withdrawals += 1;
+                stats.withdrawn = stats.withdrawn.saturating_add(amount);
+                self.store_action(
+                    transaction.tid,
+                    Action {
+                        cid: transaction.cid,
+                        kind: ActionKind::Withdrawal { amount },
+                        status: ActionStatus::Fresh,
+                        refunded: Decimal::ZERO,
+                    },
+                );
+            }
+
+            // Processing authorizations
+            // What it means:
+            // - the total amount should stay the same
+            // - held amount should go += transaction.amount
+            // meaning: the funds are reserved for a capture to come, but nothing has left yet
+            TransactionKind::Authorize { amount } => {
+                if account.get_available() < amount {
+                    return Err(Rejection::InsufficientFunds);
+                }
+                self.post(
+                    transaction.cid,
+                    LedgerAccount::Available(transaction.cid),
+                    LedgerAccount::Held(transaction.cid),
+                    amount,
+                )?;
+                self.store_action(
+                    transaction.tid,
+                    Action {
+                        cid: transaction.cid,
+                        kind: ActionKind::Authorization { amount },
+                        status: ActionStatus::Fresh,
+                        refunded: Decimal::ZERO,
+                    },
+                );
+            }
+
+            // Processing capture/void situations, both refer to an earlier authorization
+            TransactionKind::Capture { .. } | TransactionKind::Void => {
+                let Some(action) = self.actions.get(&transaction.tid) else {
+                    return Err(Rejection::UnknownTransaction);
+                };
+                if action.cid != transaction.cid {
+                    return Err(Rejection::ForeignTransaction);
+                }
+                // Only pending authorizations could be captured or voided
+                let ActionKind::Authorization { amount: authorized } = action.kind else {
+                    return Err(Rejection::NotAuthorization);
+                };
+                if action.status != ActionStatus::Fresh {
+                    return Err(Rejection::NotAuthorization);
+                }
+
+                match transaction.kind {
+                    // Capturing an authorization, settling it as a withdrawal
+                    // What it means:
+                    // - held amount should go -= authorized amount, the reservation is over
+                    // - the total amount should go -= captured amount
+                    // - whatever was authorized but not captured is available again
+                    // meaning: from now on it is a regular withdrawal, which can be disputed as such
+                    TransactionKind::Capture { amount } => {
+                        let captured = amount.unwrap_or(authorized);
+                        if captured > authorized {
+                            return Err(Rejection::CaptureExceedsAuthorization);
+                        }
+                        let velocity_point = self.check_velocity(transaction, row, captured)?;
+
+                        self.post_all(
+                            transaction.cid,
+                            &[
+                                Posting {
+                                    debit: LedgerAccount::Held(transaction.cid),
+                                    credit: LedgerAccount::House,
+                                    amount: captured,
+                                },
+                                Posting {
+                                    debit: LedgerAccount::Held(transaction.cid),
+                                    credit: LedgerAccount::Available(transaction.cid),
+                                    amount: authorized - captured,
+                                },
+                            ],
+                        )?;
+                        self.record_withdrawal(transaction.cid, velocity_point, captured);
+                        let stats = &mut self.stats[transaction.cid];
+                        stats.withdrawals += 1;
+                        stats.withdrawn = stats.withdrawn.saturating_add(captured);
+                        self.actions.insert(
+                            transaction.tid,
+                            Action {
+                                cid: transaction.cid,
+                                kind: ActionKind::Withdrawal { amount: captured },
+                                status: ActionStatus::Fresh,
+                                refunded: Decimal::ZERO,
+                            },
+                        );
+                    }
+                    // Voiding an authorization, releasing the reserved funds
+                    // What it means:
+                    // - the total amount should stay the same
+                    // - held amount should go -= authorized amount
+                    TransactionKind::Void => {
+                        self.post(
+                            transaction.cid,
+                            LedgerAccount::Held(transaction.cid),
+                            LedgerAccount::Available(transaction.cid),
+                            authorized,
+                        )?;
+                        self.set_status(transaction.tid, ActionStatus::Final);
+                    }
+                    _ => unreachable!(),
+                }
+            }
+
+            // Processing refund/reversal situations, both refer to an earlier deposit or withdrawal
+            TransactionKind::Refund { .. } | TransactionKind::Reversal => {
+                let Some(action) = self.actions.get(&transaction.tid) else {
+                    return Err(Rejection::UnknownTransaction);
+                };
+                if action.cid != transaction.cid {
+                    return Err(Rejection::ForeignTransaction);
+                }
+                // Disputed transactions are up to the dispute to settle, and final ones are settled
+                if action.status != ActionStatus::Fresh {
+                    return Err(Rejection::NotRefundable);
+                }
+                let (original, is_deposit) = match action.kind {
+                    ActionKind::Deposit { amount } => (amount, true),
+                    ActionKind::Withdrawal { amount } => (amount, false),
+                    ActionKind::Authorization { .. } => return Err(Rejection::NotRefundable),
+                };
+                let remaining = original - action.refunded;
+                let amount = match transaction.kind {
+                    TransactionKind::Refund { amount } => amount.unwrap_or(remaining),
+                    _ => remaining,
+                };
+                if amount > remaining {
+                    return Err(Rejection::RefundExceedsAmount);
+                }
+
+                let account = self.get_account_mut(transaction.cid);
+                if is_deposit {
+                    // Giving back a deposit
+                    // What it means:
+                    // - the total amount should go -= refunded amount
+                    // - held amount should stay the same
+                    // meaning: a voluntary refund is only possible out of available funds,
+                    // while a reversal is forced upon the client and might leave them owing
+                    if matches!(transaction.kind, TransactionKind::Refund { .. })
+                        && account.get_available() < amount
+                    {
+                        return Err(Rejection::InsufficientFunds);
+                    }
+                    self.post(
+                        transaction.cid,
+                        LedgerAccount::Available(transaction.cid),
+                        LedgerAccount::House,
+                        amount,
+                    )?;
+                } else {
+                    // Giving back a withdrawal
+                    // What it means:
+                    // - the total amount should go += refunded amount
+                    // - held amount should stay the same
+                    self.post(
+                        transaction.cid,
+                        LedgerAccount::House,
+                        LedgerAccount::Available(transaction.cid),
+                        amount,
+                    )?;
+                }
+
+                if let Some(action) = self.actions.get_mut(&transaction.tid) {
+                    action.refunded += amount;
+                    // Nothing left to refund or dispute once reversed or refunded in full
+                    if action.refunded == original
+                        || matches!(transaction.kind, TransactionKind::Reversal)
+                    {
+                        action.status = ActionStatus::Final;
+                    }
                 }
             }
+
             // Processing dispute/resolve/chargeback situations
             TransactionKind::Dispute | TransactionKind::Resolve | TransactionKind::Chargeback => {
-                // Check if we've seen that transaction before
+                // Check if we've seen that transaction before. Partially refunded ones are
+                // disputed for whatever is left of them, hence amount - refunded below
                 let Some(action) = self.actions.get_mut(&transaction.tid) else {
-                    return;
+                    return Err(Rejection::UnknownTransaction);
                 };
                 // Checking if that transaction belonged to the client
                 if action.cid != transaction.cid {
-                    return;
+                    return Err(Rejection::ForeignTransaction);
                 }
+                // Refunds are off while disputed, so this stays put until the dispute is over.
+                // Can't overflow the sum of open disputes, as it's all in the held funds
+                let remaining = action.remaining();
 
                 match transaction.kind {
                     TransactionKind::Dispute => {
-                        // Skipping if already disputed or final
-                        if action.status != ActionStatus::Fresh {
-                            return;
+                        if !action.is_disputable() {
+                            return Err(Rejection::NotDisputable);
                         }
-                        // This transaction is sus now, watch out
-                        action.status = ActionStatus::Disputed;
                         match action {
                             // Disputing a withdrawal transaction
                             // What it means:
@@ -90,10 +369,13 @@ impl Payments {
                                 kind: ActionKind::Withdrawal { amount },
                                 ..
                             } => {
-                                let amount = *amount;
-                                let account = self.get_account_mut(transaction.cid);
-                                account.total += amount;
-                                account.held += amount;
+                                let amount = *amount - action.refunded;
+                                self.post(
+                                    transaction.cid,
+                                    LedgerAccount::House,
+                                    LedgerAccount::Held(transaction.cid),
+                                    amount,
+                                )?;
                             }
                             // Disputing a deposit transaction
                             // What it means:
@@ -107,18 +389,31 @@ impl Payments {
                                 kind: ActionKind::Deposit { amount },
                                 ..
                             } => {
-                                let amount = *amount;
-                                let account = self.get_account_mut(transaction.cid);
-                                account.held += amount;
+                                let amount = *amount - action.refunded;
+                                self.post(
+                                    transaction.cid,
+                                    LedgerAccount::Available(transaction.cid),
+                                    LedgerAccount::Held(transaction.cid),
+                                    amount,
+                                )?;
                             }
+                            // Pending authorizations never make it to a dispute, see above
+                            Action {
+                                kind: ActionKind::Authorization { .. },
+                                ..
+                            } => unreachable!(),
                         }
+                        // This transaction is sus now, watch out
+                        self.set_status(transaction.tid, ActionStatus::Disputed);
+                        let stats = &mut self.stats[transaction.cid];
+                        stats.open_disputes += 1;
+                        stats.disputed += remaining;
                     }
                     TransactionKind::Resolve => {
                         // Cant resolve what's not disputed, right?
                         if action.status != ActionStatus::Disputed {
-                            return;
+                            return Err(Rejection::NotDisputed);
                         }
-                        action.status = ActionStatus::Final;
                         match action {
                             // Resolving a withdrawal transaction, reverting the transaction
                             // What it means:
@@ -131,9 +426,13 @@ impl Payments {
                                 kind: ActionKind::Withdrawal { amount },
                                 ..
                             } => {
-                                let amount = *amount;
-                                let account = self.get_account_mut(transaction.cid);
-                                account.held -= amount;
+                                let amount = *amount - action.refunded;
+                                self.post(
+                                    transaction.cid,
+                                    LedgerAccount::Held(transaction.cid),
+                                    LedgerAccount::Available(transaction.cid),
+                                    amount,
+                                )?;
                             }
                             // Resolving a deposit transaction, reverting the transaction
                             // What it means:
@@ -145,19 +444,30 @@ impl Payments {
                                 kind: ActionKind::Deposit { amount },
                                 ..
                             } => {
-                                let amount = *amount;
-                                let account = self.get_account_mut(transaction.cid);
-                                account.total -= amount;
-                                account.held -= amount;
+                                let amount = *amount - action.refunded;
+                                self.post(
+                                    transaction.cid,
+                                    LedgerAccount::Held(transaction.cid),
+                                    LedgerAccount::House,
+                                    amount,
+                                )?;
                             }
+                            // Pending authorizations never make it to a dispute, see above
+                            Action {
+                                kind: ActionKind::Authorization { .. },
+                                ..
+                            } => unreachable!(),
                         }
+                        self.set_status(transaction.tid, ActionStatus::Reverted);
+                        let stats = &mut self.stats[transaction.cid];
+                        stats.open_disputes -= 1;
+                        stats.disputed -= remaining;
                     }
                     TransactionKind::Chargeback => {
                         // Cant resolve what's not disputed, right?
                         if action.status != ActionStatus::Disputed {
-                            return;
+                            return Err(Rejection::NotDisputed);
                         }
-                        action.status = ActionStatus::Final;
                         match action {
                             // Charging back a withdrawal transaction: forcing the transaction
                             // What it means:
@@ -168,11 +478,14 @@ impl Payments {
                                 kind: ActionKind::Withdrawal { amount },
                                 ..
                             } => {
-                                let amount = *amount;
-                                let account = self.get_account_mut(transaction.cid);
-                                account.held -= amount;
-                                account.total -= amount;
-                                account.is_locked = true;
+                                let amount = *amount - action.refunded;
+                                self.post(
+                                    transaction.cid,
+                                    LedgerAccount::Held(transaction.cid),
+                                    LedgerAccount::House,
+                                    amount,
+                                )?;
+                                self.get_account_mut(transaction.cid).is_locked = true;
                             }
                             // Charging back a deposit transaction: forcing the transaction
                             // What it means:
@@ -183,23 +496,149 @@ impl Payments {
                                 kind: ActionKind::Deposit { amount },
                                 ..
                             } => {
-                                let amount = *amount;
-                                let account = self.get_account_mut(transaction.cid);
-                                account.held -= amount;
-                                account.is_locked = true;
+                                let amount = *amount - action.refunded;
+                                self.post(
+                                    transaction.cid,
+                                    LedgerAccount::Held(transaction.cid),
+                                    LedgerAccount::Available(transaction.cid),
+                                    amount,
+                                )?;
+                                self.get_account_mut(transaction.cid).is_locked = true;
                             }
+                            // Pending authorizations never make it to a dispute, see above
+                            Action {
+                                kind: ActionKind::Authorization { .. },
+                                ..
+                            } => unreachable!(),
                         }
+                        self.set_status(transaction.tid, ActionStatus::Final);
+                        let stats = &mut self.stats[transaction.cid];
+                        stats.open_disputes -= 1;
+                        stats.disputed -= remaining;
+                        stats.chargebacks += 1;
                     }
                     _ => unreachable!(),
                 }
             }
         }
+
+        Ok(())
+    }
+
+    fn apply_rules(&mut self, transaction: &Transaction) -> Result<(), Rejection> {
+        let cid = transaction.cid;
+        let context = RuleContext {
+            transaction,
+            account: &self.accounts[cid],
+            stats: &self.stats[cid],
+            actions: &self.actions,
+            recent_actions: self.recent_actions.get(&transaction.cid),
+        };
+
+        let mut lock = None;
+        for rule in &self.rules {
+            let (message, rejection) = match rule.evaluate(&context) {
+                Verdict::Accept => continue,
+                Verdict::Flag(message) => (message, None),
+                Verdict::Reject(message) => (message, Some(Rejection::Rule(rule.name()))),
+                Verdict::Lock(message) => {
+                    lock = Some(Rejection::Rule(rule.name()));
+                    (message, lock)
+                }
+            };
+            self.alerts.push(Alert {
+                tid: transaction.tid,
+                cid: transaction.cid,
+                rule: rule.name(),
+                message,
+            });
+            if let Some(rejection) = rejection {
+                if lock.is_some() {
+                    self.get_account_mut(transaction.cid).is_locked = true;
+                }
+                return Err(rejection);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn post(
+        &mut self,
+        cid: ClientId,
+        debit: LedgerAccount,
+        credit: LedgerAccount,
+        amount: Decimal,
+    ) -> Result<(), Rejection> {
+        self.post_all(
+            cid,
+            &[Posting {
+                debit,
+                credit,
+                amount,
+            }],
+        )
+    }
+
+    // Records the balance changes in the ledger and derives the client's account from it.
+    // Should any of the balances overflow, nothing gets recorded at all, that's why it
+    // has to go before any other change the transaction makes
+    fn post_all(&mut self, cid: ClientId, postings: &[Posting]) -> Result<(), Rejection> {
+        let prepared = self.ledger.prepare(postings)?;
+        let available = prepared.balance(&self.ledger, LedgerAccount::Available(cid));
+        let held = prepared.balance(&self.ledger, LedgerAccount::Held(cid));
+        let total = available.checked_add(held).ok_or(Rejection::Overflow)?;
+        self.ledger.commit(prepared);
+
+        let account = self.get_account_mut(cid);
+        account.held = held;
+        account.total = total;
+        Ok(())
+    }
+
+    fn set_status(&mut self, tid: TransactionId, status: ActionStatus) {
+        if let Some(action) = self.actions.get_mut(&tid) {
+            action.status = status;
+        }
+    }
+
+    fn store_action(&mut self, tid: TransactionId, action: Action) {
+        let recent = self.recent_actions.entry(action.cid).or_default();
+        if recent.len() == RECENT_ACTIONS_DEPTH {
+            recent.pop_front();
+        }
+        recent.push_back(tid);
+        self.actions.insert(tid, action);
+    }
+
+    // Gives back the point the withdrawal should be recorded at once it goes through,
+    // if the client has a limit at all
+    fn check_velocity(
+        &mut self,
+        transaction: &Transaction,
+        row: u64,
+        amount: Decimal,
+    ) -> Result<Option<u64>, Rejection> {
+        let Some(limit) = self.limits.get(&transaction.cid) else {
+            return Ok(None);
+        };
+        let point = limit.point(row, transaction.timestamp)?;
+        let window = self.withdrawal_windows.entry(transaction.cid).or_default();
+        limit.check(window, point, amount)?;
+        Ok(Some(point))
+    }
+
+    fn record_withdrawal(&mut self, cid: ClientId, point: Option<u64>, amount: Decimal) {
+        if let Some(point) = point {
+            self.withdrawal_windows
+                .entry(cid)
+                .or_default()
+                .record(point, amount);
+        }
     }
 
-    // SAFETY: we are preinitializing the whole list of accounts at start, so indexing
-    // like this will always succeed
-    fn get_account_mut(&mut self, cid: u16) -> &mut Account {
-        unsafe { self.accounts.get_unchecked_mut(cid as usize) }
+    fn get_account_mut(&mut self, cid: ClientId) -> &mut Account {
+        &mut self.accounts[cid]
     }
 }
 
@@ -207,15 +646,14 @@ impl Payments {
 mod tests {
 
     use super::*;
+    use crate::id::tid;
     use rust_decimal_macros::dec;
 
-    fn get_active_accounts(payments: &Payments) -> Vec<(u16, Account)> {
+    fn get_active_accounts(payments: &Payments) -> Vec<(ClientId, Account)> {
         payments
             .accounts
-            .clone()
             .iter()
-            .enumerate()
-            .map(|(index, account)| (index as u16, *account))
+            .map(|(cid, account)| (cid, *account))
             .filter(|(_, account)| account.has_activity)
             .collect()
     }
@@ -226,18 +664,20 @@ mod tests {
         let transactions = vec![
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Deposit { amount: dec!(10.0) },
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Deposit { amount: dec!(20.0) },
             },
         ];
 
         for transaction in transactions {
-            payments.process_transaction(&transaction);
+            let _ = payments.process_transaction(&transaction);
         }
 
         let active_clients = get_active_accounts(&payments);
@@ -261,18 +701,20 @@ mod tests {
         let transactions = vec![
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Deposit { amount: dec!(10.0) },
             },
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Dispute,
             },
         ];
 
         for transaction in transactions {
-            payments.process_transaction(&transaction);
+            let _ = payments.process_transaction(&transaction);
         }
 
         let active_clients = get_active_accounts(&payments);
@@ -296,23 +738,26 @@ mod tests {
         let transactions = vec![
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Deposit { amount: dec!(10.0) },
             },
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Dispute,
             },
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Resolve,
             },
         ];
 
         for transaction in transactions {
-            payments.process_transaction(&transaction);
+            let _ = payments.process_transaction(&transaction);
         }
 
         let active_clients = get_active_accounts(&payments);
@@ -336,23 +781,26 @@ mod tests {
         let transactions = vec![
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Deposit { amount: dec!(10.0) },
             },
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Dispute,
             },
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Chargeback,
             },
         ];
 
         for transaction in transactions {
-            payments.process_transaction(&transaction);
+            let _ = payments.process_transaction(&transaction);
         }
 
         let active_clients = get_active_accounts(&payments);
@@ -376,18 +824,20 @@ mod tests {
         let transactions = vec![
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Deposit { amount: dec!(20.0) },
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Withdrawal { amount: dec!(15.0) },
             },
         ];
 
         for transaction in transactions {
-            payments.process_transaction(&transaction);
+            let _ = payments.process_transaction(&transaction);
         }
 
         let active_clients = get_active_accounts(&payments);
@@ -412,18 +862,20 @@ mod tests {
         let transactions = vec![
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Deposit { amount: dec!(20.0) },
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Withdrawal { amount: dec!(25.0) },
             },
         ];
 
         for transaction in transactions {
-            payments.process_transaction(&transaction);
+            let _ = payments.process_transaction(&transaction);
         }
 
         let active_clients = get_active_accounts(&payments);
@@ -447,23 +899,26 @@ mod tests {
         let transactions = vec![
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Deposit { amount: dec!(10.0) },
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Withdrawal { amount: dec!(5.0) },
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Dispute,
             },
         ];
 
         for transaction in transactions {
-            payments.process_transaction(&transaction);
+            let _ = payments.process_transaction(&transaction);
         }
 
         let active_clients = get_active_accounts(&payments);
@@ -487,28 +942,32 @@ mod tests {
         let transactions = vec![
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Deposit { amount: dec!(10.0) },
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Withdrawal { amount: dec!(5.0) },
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Dispute,
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Resolve,
             },
         ];
 
         for transaction in transactions {
-            payments.process_transaction(&transaction);
+            let _ = payments.process_transaction(&transaction);
         }
 
         let active_clients = get_active_accounts(&payments);
@@ -532,28 +991,32 @@ mod tests {
         let transactions = vec![
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Deposit { amount: dec!(10.0) },
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Withdrawal { amount: dec!(5.0) },
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Dispute,
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Chargeback,
             },
         ];
 
         for transaction in transactions {
-            payments.process_transaction(&transaction);
+            let _ = payments.process_transaction(&transaction);
         }
 
         let active_clients = get_active_accounts(&payments);
@@ -578,28 +1041,32 @@ mod tests {
         let transactions = vec![
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Deposit { amount: dec!(10.0) },
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Withdrawal { amount: dec!(20.0) },
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Dispute,
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Resolve,
             },
         ];
 
         for transaction in transactions {
-            payments.process_transaction(&transaction);
+            let _ = payments.process_transaction(&transaction);
         }
 
         assert_eq!(
@@ -622,32 +1089,36 @@ mod tests {
         let transactions = vec![
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Deposit {
                     amount: dec!(100.0),
                 },
             },
             Transaction {
                 cid: 1,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Deposit {
                     amount: dec!(200.0),
                 },
             },
             Transaction {
                 cid: 0,
-                tid: 2,
+                tid: tid(2),
+                timestamp: None,
                 kind: TransactionKind::Withdrawal { amount: dec!(50.0) },
             },
             Transaction {
                 cid: 1,
-                tid: 3,
+                tid: tid(3),
+                timestamp: None,
                 kind: TransactionKind::Withdrawal { amount: dec!(75.0) },
             },
         ];
 
         for transaction in transactions {
-            payments.process_transaction(&transaction);
+            let _ = payments.process_transaction(&transaction);
         }
 
         let active_clients = get_active_accounts(&payments);
@@ -685,7 +1156,8 @@ mod tests {
         let transactions = vec![
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Deposit {
                     amount: dec!(100.0),
                 },
@@ -693,13 +1165,14 @@ mod tests {
             // Client 1 tries to dispute client 0's deposit
             Transaction {
                 cid: 1,
-                tid: 0, // Same tid as client 0's deposit
+                tid: tid(0), // Same tid as client 0's deposit
+                timestamp: None,
                 kind: TransactionKind::Dispute,
             },
         ];
 
         for transaction in transactions {
-            payments.process_transaction(&transaction);
+            let _ = payments.process_transaction(&transaction);
         }
 
         assert_eq!(
@@ -734,30 +1207,35 @@ mod tests {
             // Client 0 setup
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Deposit {
                     amount: dec!(100.0),
                 },
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Withdrawal { amount: dec!(50.0) },
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Dispute,
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Chargeback, // Locks client 0
             },
             // Client 1 setup
             Transaction {
                 cid: 1,
-                tid: 2,
+                tid: tid(2),
+                timestamp: None,
                 kind: TransactionKind::Deposit {
                     amount: dec!(200.0),
                 },
@@ -765,7 +1243,8 @@ mod tests {
             // Try to do more with client 0 - should all fail
             Transaction {
                 cid: 0,
-                tid: 3,
+                tid: tid(3),
+                timestamp: None,
                 kind: TransactionKind::Deposit {
                     amount: dec!(1000.0),
                 },
@@ -773,13 +1252,14 @@ mod tests {
             // Client 1 continues normally
             Transaction {
                 cid: 1,
-                tid: 4,
+                tid: tid(4),
+                timestamp: None,
                 kind: TransactionKind::Withdrawal { amount: dec!(50.0) },
             },
         ];
 
         for transaction in transactions {
-            payments.process_transaction(&transaction);
+            let _ = payments.process_transaction(&transaction);
         }
 
         // Client 0 is locked, deposit didn't go through
@@ -816,35 +1296,40 @@ mod tests {
         let transactions = vec![
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Deposit {
                     amount: dec!(100.0),
                 },
             },
             Transaction {
                 cid: 0,
-                tid: 1,
+                tid: tid(1),
+                timestamp: None,
                 kind: TransactionKind::Withdrawal { amount: dec!(50.0) },
             },
             Transaction {
                 cid: 0,
-                tid: 2,
+                tid: tid(2),
+                timestamp: None,
                 kind: TransactionKind::Withdrawal { amount: dec!(50.0) },
             },
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Dispute,
             },
             Transaction {
                 cid: 0,
-                tid: 0,
+                tid: tid(0),
+                timestamp: None,
                 kind: TransactionKind::Resolve,
             },
         ];
 
         for transaction in transactions {
-            payments.process_transaction(&transaction);
+            let _ = payments.process_transaction(&transaction);
         }
 
         // Client 0 is locked, deposit didn't go through
@@ -861,4 +1346,915 @@ mod tests {
             )]
         );
     }
+
+    #[test]
+    fn test_velocity_count_limit() {
+        let mut payments = Payments::default();
+        payments.limits.insert(
+            0,
+            VelocityLimit {
+                window: 10,
+                unit: crate::limits::WindowUnit::Rows,
+                max_count: Some(1),
+                max_amount: None,
+            },
+        );
+
+        let deposit = Transaction {
+            cid: 0,
+            tid: tid(0),
+            timestamp: None,
+            kind: TransactionKind::Deposit {
+                amount: dec!(100.0),
+            },
+        };
+        let first = Transaction {
+            cid: 0,
+            tid: tid(1),
+            timestamp: None,
+            kind: TransactionKind::Withdrawal { amount: dec!(10.0) },
+        };
+        let second = Transaction {
+            cid: 0,
+            tid: tid(2),
+            timestamp: None,
+            kind: TransactionKind::Withdrawal { amount: dec!(10.0) },
+        };
+
+        assert_eq!(payments.process_transaction(&deposit), Ok(()));
+        assert_eq!(payments.process_transaction(&first), Ok(()));
+        assert_eq!(
+            payments.process_transaction(&second),
+            Err(Rejection::VelocityCountExceeded)
+        );
+        assert_eq!(payments.accounts[0].total, dec!(90.0));
+    }
+
+    // Withdrawals which failed for lack of funds should not eat up the limit
+    #[test]
+    fn test_velocity_ignores_failed_withdrawals() {
+        let mut payments = Payments::default();
+        payments.limits.insert(
+            0,
+            VelocityLimit {
+                window: 3600,
+                unit: crate::limits::WindowUnit::Seconds,
+                max_count: None,
+                max_amount: Some(dec!(50.0)),
+            },
+        );
+
+        let transactions = [
+            Transaction {
+                cid: 0,
+                tid: tid(0),
+                timestamp: Some(100),
+                kind: TransactionKind::Deposit { amount: dec!(40.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(1),
+                timestamp: Some(200),
+                kind: TransactionKind::Withdrawal { amount: dec!(45.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(2),
+                timestamp: Some(300),
+                kind: TransactionKind::Deposit { amount: dec!(40.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(3),
+                timestamp: Some(400),
+                kind: TransactionKind::Withdrawal { amount: dec!(45.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(4),
+                timestamp: Some(500),
+                kind: TransactionKind::Withdrawal { amount: dec!(10.0) },
+            },
+        ];
+
+        let results: Vec<_> = transactions
+            .iter()
+            .map(|transaction| payments.process_transaction(transaction))
+            .collect();
+
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Err(Rejection::InsufficientFunds),
+                Ok(()),
+                Ok(()),
+                Err(Rejection::VelocityAmountExceeded),
+            ]
+        );
+        assert_eq!(payments.accounts[0].total, dec!(35.0));
+    }
+
+    #[test]
+    fn test_rules_flag_and_lock() {
+        struct LockOnThirdDeposit;
+
+        impl Rule for LockOnThirdDeposit {
+            fn name(&self) -> &'static str {
+                "lock-on-third-deposit"
+            }
+
+            fn evaluate(&self, context: &RuleContext) -> Verdict {
+                match context.stats.deposits {
+                    0 => Verdict::Accept,
+                    1 => Verdict::Flag("second deposit".to_string()),
+                    _ => Verdict::Lock("third deposit".to_string()),
+                }
+            }
+        }
+
+        let mut payments = Payments::default();
+        payments.rules.push(Box::new(LockOnThirdDeposit));
+
+        let results: Vec<_> = (0..3)
+            .map(|id| {
+                payments.process_transaction(&Transaction {
+                    cid: 0,
+                    tid: tid(id),
+                    timestamp: None,
+                    kind: TransactionKind::Deposit { amount: dec!(10.0) },
+                })
+            })
+            .collect();
+
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Ok(()),
+                Err(Rejection::Rule("lock-on-third-deposit"))
+            ]
+        );
+        assert_eq!(
+            payments
+                .alerts
+                .iter()
+                .map(|alert| alert.tid)
+                .collect::<Vec<_>>(),
+            vec![tid(1), tid(2)]
+        );
+        assert_eq!(
+            get_active_accounts(&payments),
+            vec![(
+                0,
+                Account {
+                    total: dec!(20.0),
+                    held: dec!(0),
+                    is_locked: true,
+                    has_activity: true
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_chargeback_ratio_locks_on_disputes() {
+        let mut payments = Payments {
+            rules: crate::rules::default_rules(),
+            ..Default::default()
+        };
+        for id in 0..10 {
+            payments
+                .process_transaction(&Transaction {
+                    cid: 0,
+                    tid: tid(id),
+                    timestamp: None,
+                    kind: TransactionKind::Deposit { amount: dec!(1.0) },
+                })
+                .unwrap();
+        }
+        let dispute = |id| Transaction {
+            cid: 0,
+            tid: tid(id),
+            timestamp: None,
+            kind: TransactionKind::Dispute,
+        };
+
+        assert_eq!(payments.process_transaction(&dispute(0)), Ok(()));
+        // Disputes the engine refuses anyway make no difference to the ratio
+        assert_eq!(
+            payments.process_transaction(&dispute(999)),
+            Err(Rejection::UnknownTransaction)
+        );
+        assert_eq!(
+            payments.process_transaction(&dispute(0)),
+            Err(Rejection::NotDisputable)
+        );
+        assert_eq!(
+            payments.process_transaction(&dispute(1)),
+            Err(Rejection::Rule("chargeback-ratio"))
+        );
+        assert_eq!(
+            payments
+                .alerts
+                .iter()
+                .map(|alert| (alert.tid, alert.rule))
+                .collect::<Vec<_>>(),
+            vec![(tid(1), "chargeback-ratio")]
+        );
+        assert_eq!(
+            get_active_accounts(&payments),
+            vec![(
+                0,
+                Account {
+                    total: dec!(10.0),
+                    held: dec!(1.0),
+                    is_locked: true,
+                    has_activity: true
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_cross_client_dispute_is_flagged() {
+        let mut payments = Payments {
+            rules: crate::rules::default_rules(),
+            ..Default::default()
+        };
+        payments
+            .process_transaction(&Transaction {
+                cid: 0,
+                tid: tid(0),
+                timestamp: None,
+                kind: TransactionKind::Deposit { amount: dec!(1.0) },
+            })
+            .unwrap();
+
+        // Still the rejection the engine would give, along with an alert
+        assert_eq!(
+            payments.process_transaction(&Transaction {
+                cid: 1,
+                tid: tid(0),
+                timestamp: None,
+                kind: TransactionKind::Dispute,
+            }),
+            Err(Rejection::ForeignTransaction)
+        );
+        assert_eq!(
+            payments
+                .alerts
+                .iter()
+                .map(|alert| (alert.tid, alert.cid, alert.rule))
+                .collect::<Vec<_>>(),
+            vec![(tid(0), 1, "cross-client-dispute")]
+        );
+    }
+
+    #[test]
+    fn test_authorize() {
+        let mut payments = Payments::default();
+        let transactions = vec![
+            Transaction {
+                cid: 0,
+                tid: tid(0),
+                timestamp: None,
+                kind: TransactionKind::Deposit { amount: dec!(10.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(1),
+                timestamp: None,
+                kind: TransactionKind::Authorize { amount: dec!(4.0) },
+            },
+        ];
+
+        for transaction in transactions {
+            let _ = payments.process_transaction(&transaction);
+        }
+
+        assert_eq!(
+            get_active_accounts(&payments),
+            vec![(
+                0,
+                Account {
+                    total: dec!(10.0),
+                    held: dec!(4.0),
+                    is_locked: false,
+                    has_activity: true
+                }
+            )]
+        );
+    }
+
+    // Whatever is left after a partial capture goes back to the available pool
+    #[test]
+    fn test_authorize_partial_capture() {
+        let mut payments = Payments::default();
+        let transactions = vec![
+            Transaction {
+                cid: 0,
+                tid: tid(0),
+                timestamp: None,
+                kind: TransactionKind::Deposit { amount: dec!(10.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(1),
+                timestamp: None,
+                kind: TransactionKind::Authorize { amount: dec!(4.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(1),
+                timestamp: None,
+                kind: TransactionKind::Capture {
+                    amount: Some(dec!(3.0)),
+                },
+            },
+        ];
+
+        for transaction in transactions {
+            let _ = payments.process_transaction(&transaction);
+        }
+
+        assert_eq!(
+            get_active_accounts(&payments),
+            vec![(
+                0,
+                Account {
+                    total: dec!(7.0),
+                    held: dec!(0.0),
+                    is_locked: false,
+                    has_activity: true
+                }
+            )]
+        );
+    }
+
+    // Once captured, the authorization is just a withdrawal, disputes included
+    #[test]
+    fn test_captured_authorization_dispute() {
+        let mut payments = Payments::default();
+        let transactions = vec![
+            Transaction {
+                cid: 0,
+                tid: tid(0),
+                timestamp: None,
+                kind: TransactionKind::Deposit { amount: dec!(10.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(1),
+                timestamp: None,
+                kind: TransactionKind::Authorize { amount: dec!(4.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(1),
+                timestamp: None,
+                kind: TransactionKind::Capture { amount: None },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(1),
+                timestamp: None,
+                kind: TransactionKind::Dispute,
+            },
+        ];
+
+        for transaction in transactions {
+            let _ = payments.process_transaction(&transaction);
+        }
+
+        assert_eq!(
+            get_active_accounts(&payments),
+            vec![(
+                0,
+                Account {
+                    total: dec!(10.0),
+                    held: dec!(4.0),
+                    is_locked: false,
+                    has_activity: true
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_authorize_void() {
+        let mut payments = Payments::default();
+        let transactions = vec![
+            Transaction {
+                cid: 0,
+                tid: tid(0),
+                timestamp: None,
+                kind: TransactionKind::Deposit { amount: dec!(10.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(1),
+                timestamp: None,
+                kind: TransactionKind::Authorize { amount: dec!(4.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(1),
+                timestamp: None,
+                kind: TransactionKind::Void,
+            },
+        ];
+
+        for transaction in transactions {
+            let _ = payments.process_transaction(&transaction);
+        }
+
+        assert_eq!(
+            get_active_accounts(&payments),
+            vec![(
+                0,
+                Account {
+                    total: dec!(10.0),
+                    held: dec!(0.0),
+                    is_locked: false,
+                    has_activity: true
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_authorization_rejections() {
+        let mut payments = Payments::default();
+        let deposit = Transaction {
+            cid: 0,
+            tid: tid(0),
+            timestamp: None,
+            kind: TransactionKind::Deposit { amount: dec!(10.0) },
+        };
+        let authorize = |amount| Transaction {
+            cid: 0,
+            tid: tid(1),
+            timestamp: None,
+            kind: TransactionKind::Authorize { amount },
+        };
+        let capture = |amount| Transaction {
+            cid: 0,
+            tid: tid(1),
+            timestamp: None,
+            kind: TransactionKind::Capture { amount },
+        };
+        let dispute = Transaction {
+            cid: 0,
+            tid: tid(1),
+            timestamp: None,
+            kind: TransactionKind::Dispute,
+        };
+        let void_deposit = Transaction {
+            cid: 0,
+            tid: tid(0),
+            timestamp: None,
+            kind: TransactionKind::Void,
+        };
+
+        assert_eq!(payments.process_transaction(&deposit), Ok(()));
+        assert_eq!(
+            payments.process_transaction(&authorize(dec!(11.0))),
+            Err(Rejection::InsufficientFunds)
+        );
+        assert_eq!(payments.process_transaction(&authorize(dec!(5.0))), Ok(()));
+        assert_eq!(
+            payments.process_transaction(&dispute),
+            Err(Rejection::NotDisputable)
+        );
+        assert_eq!(
+            payments.process_transaction(&void_deposit),
+            Err(Rejection::NotAuthorization)
+        );
+        assert_eq!(
+            payments.process_transaction(&capture(Some(dec!(6.0)))),
+            Err(Rejection::CaptureExceedsAuthorization)
+        );
+        assert_eq!(payments.process_transaction(&capture(None)), Ok(()));
+        assert_eq!(
+            payments.process_transaction(&capture(None)),
+            Err(Rejection::NotAuthorization)
+        );
+        assert_eq!(payments.accounts[0].total, dec!(5.0));
+        assert_eq!(payments.accounts[0].held, dec!(0.0));
+    }
+
+    // Only what is left of a partially refunded deposit gets held by a dispute
+    #[test]
+    fn test_partial_refund_then_dispute() {
+        let mut payments = Payments::default();
+        let transactions = vec![
+            Transaction {
+                cid: 0,
+                tid: tid(0),
+                timestamp: None,
+                kind: TransactionKind::Deposit { amount: dec!(10.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(0),
+                timestamp: None,
+                kind: TransactionKind::Refund {
+                    amount: Some(dec!(4.0)),
+                },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(0),
+                timestamp: None,
+                kind: TransactionKind::Dispute,
+            },
+        ];
+
+        for transaction in transactions {
+            let _ = payments.process_transaction(&transaction);
+        }
+
+        assert_eq!(
+            get_active_accounts(&payments),
+            vec![(
+                0,
+                Account {
+                    total: dec!(6.0),
+                    held: dec!(6.0),
+                    is_locked: false,
+                    has_activity: true
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_withdrawal_refund() {
+        let mut payments = Payments::default();
+        let transactions = vec![
+            Transaction {
+                cid: 0,
+                tid: tid(0),
+                timestamp: None,
+                kind: TransactionKind::Deposit { amount: dec!(10.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(1),
+                timestamp: None,
+                kind: TransactionKind::Withdrawal { amount: dec!(8.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(1),
+                timestamp: None,
+                kind: TransactionKind::Refund { amount: None },
+            },
+        ];
+
+        for transaction in transactions {
+            let _ = payments.process_transaction(&transaction);
+        }
+
+        assert_eq!(
+            get_active_accounts(&payments),
+            vec![(
+                0,
+                Account {
+                    total: dec!(10.0),
+                    held: dec!(0.0),
+                    is_locked: false,
+                    has_activity: true
+                }
+            )]
+        );
+    }
+
+    // A reversal is forced, it might leave the client owing money
+    #[test]
+    fn test_deposit_reversal_overdraft() {
+        let mut payments = Payments::default();
+        let transactions = vec![
+            Transaction {
+                cid: 0,
+                tid: tid(0),
+                timestamp: None,
+                kind: TransactionKind::Deposit { amount: dec!(10.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(1),
+                timestamp: None,
+                kind: TransactionKind::Withdrawal { amount: dec!(8.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(0),
+                timestamp: None,
+                kind: TransactionKind::Reversal,
+            },
+        ];
+
+        for transaction in transactions {
+            let _ = payments.process_transaction(&transaction);
+        }
+
+        assert_eq!(
+            get_active_accounts(&payments),
+            vec![(
+                0,
+                Account {
+                    total: dec!(-8.0),
+                    held: dec!(0.0),
+                    is_locked: false,
+                    has_activity: true
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_refund_rejections() {
+        let mut payments = Payments::default();
+        let deposit = |id| Transaction {
+            cid: 0,
+            tid: tid(id),
+            timestamp: None,
+            kind: TransactionKind::Deposit { amount: dec!(10.0) },
+        };
+        let refund = |id, amount| Transaction {
+            cid: 0,
+            tid: tid(id),
+            timestamp: None,
+            kind: TransactionKind::Refund { amount },
+        };
+        let dispute = |id| Transaction {
+            cid: 0,
+            tid: tid(id),
+            timestamp: None,
+            kind: TransactionKind::Dispute,
+        };
+        let reversal = |id| Transaction {
+            cid: 0,
+            tid: tid(id),
+            timestamp: None,
+            kind: TransactionKind::Reversal,
+        };
+
+        assert_eq!(payments.process_transaction(&deposit(0)), Ok(()));
+        assert_eq!(
+            payments.process_transaction(&refund(0, Some(dec!(11.0)))),
+            Err(Rejection::RefundExceedsAmount)
+        );
+        assert_eq!(
+            payments.process_transaction(&refund(0, Some(dec!(6.0)))),
+            Ok(())
+        );
+        assert_eq!(
+            payments.process_transaction(&refund(0, Some(dec!(5.0)))),
+            Err(Rejection::RefundExceedsAmount)
+        );
+        assert_eq!(payments.process_transaction(&refund(0, None)), Ok(()));
+        // Refunded in full, nothing left to dispute
+        assert_eq!(
+            payments.process_transaction(&dispute(0)),
+            Err(Rejection::NotDisputable)
+        );
+
+        assert_eq!(payments.process_transaction(&deposit(1)), Ok(()));
+        assert_eq!(payments.process_transaction(&dispute(1)), Ok(()));
+        assert_eq!(
+            payments.process_transaction(&reversal(1)),
+            Err(Rejection::NotRefundable)
+        );
+        assert_eq!(
+            payments.process_transaction(&refund(2, None)),
+            Err(Rejection::UnknownTransaction)
+        );
+
+        assert_eq!(payments.accounts[0].total, dec!(10.0));
+        assert_eq!(payments.accounts[0].held, dec!(10.0));
+    }
+
+    // Whatever happens, the house owes the clients exactly what their accounts say
+    #[test]
+    fn test_ledger_mirrors_accounts() {
+        let mut payments = Payments::default();
+        let transactions = vec![
+            Transaction {
+                cid: 0,
+                tid: tid(0),
+                timestamp: None,
+                kind: TransactionKind::Deposit { amount: dec!(10.0) },
+            },
+            Transaction {
+                cid: 1,
+                tid: tid(1),
+                timestamp: None,
+                kind: TransactionKind::Deposit { amount: dec!(7.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(2),
+                timestamp: None,
+                kind: TransactionKind::Withdrawal { amount: dec!(3.0) },
+            },
+            Transaction {
+                cid: 0,
+                tid: tid(2),
+                timestamp: None,
+                kind: TransactionKind::Dispute,
+            },
+            Transaction {
+                cid: 1,
+                tid: tid(3),
+                timestamp: None,
+                kind: TransactionKind::Authorize { amount: dec!(5.0) },
+            },
+            Transaction {
+                cid: 1,
+                tid: tid(1),
+                timestamp: None,
+                kind: TransactionKind::Dispute,
+            },
+            Transaction {
+                cid: 1,
+                tid: tid(1),
+                timestamp: None,
+                kind: TransactionKind::Resolve,
+            },
+        ];
+
+        for transaction in transactions {
+            let _ = payments.process_transaction(&transaction);
+        }
+
+        let totals = payments.ledger.totals().unwrap();
+        assert_eq!(totals.debits, totals.credits);
+        for (cid, account) in get_active_accounts(&payments) {
+            assert_eq!(
+                account.held,
+                payments.ledger.balance(LedgerAccount::Held(cid))
+            );
+            assert_eq!(
+                account.get_available(),
+                payments.ledger.balance(LedgerAccount::Available(cid))
+            );
+        }
+        assert_eq!(
+            payments.ledger.balance(LedgerAccount::House),
+            -get_active_accounts(&payments)
+                .iter()
+                .map(|(_, account)| account.total)
+                .sum::<Decimal>()
+        );
+        assert_eq!(payments.accounts[1].total, dec!(0.0));
+        assert_eq!(payments.accounts[1].held, dec!(5.0));
+    }
+
+    // Huge amounts must not bring the whole run down, only the transaction at fault
+    #[test]
+    fn test_overflow_is_rejected() {
+        let mut payments = Payments::default();
+        let transaction = |cid, id, kind| Transaction {
+            cid,
+            tid: tid(id),
+            timestamp: None,
+            kind,
+        };
+        let deposit = |cid, id, amount| transaction(cid, id, TransactionKind::Deposit { amount });
+        let withdrawal =
+            |cid, id, amount| transaction(cid, id, TransactionKind::Withdrawal { amount });
+
+        assert_eq!(
+            payments.process_transaction(&deposit(0, 0, Decimal::MAX)),
+            Ok(())
+        );
+        assert_eq!(
+            payments.process_transaction(&deposit(0, 1, Decimal::MAX)),
+            Err(Rejection::Overflow)
+        );
+        // Only the balances are limited, no matter how much money has gone through
+        for round in 0..7 {
+            assert_eq!(
+                payments.process_transaction(&deposit(2, 10 + 2 * round, Decimal::MAX)),
+                Ok(())
+            );
+            assert_eq!(
+                payments.process_transaction(&withdrawal(2, 11 + 2 * round, Decimal::MAX)),
+                Ok(())
+            );
+        }
+        // Nor by what the other clients hold
+        assert_eq!(
+            payments.process_transaction(&deposit(1, 2, Decimal::MAX)),
+            Ok(())
+        );
+        assert_eq!(
+            payments.process_transaction(&withdrawal(0, 3, dec!(1.0))),
+            Ok(())
+        );
+        // Holding the whole deposit takes the available funds below zero, which still fits
+        assert_eq!(
+            payments.process_transaction(&transaction(0, 0, TransactionKind::Dispute)),
+            Ok(())
+        );
+        assert_eq!(
+            payments.process_transaction(&deposit(0, 4, dec!(1.0))),
+            Ok(())
+        );
+        // The total would not, available and held together are more than a Decimal holds
+        assert_eq!(
+            payments.process_transaction(&deposit(0, 5, dec!(1.0))),
+            Err(Rejection::Overflow)
+        );
+
+        assert_eq!(
+            get_active_accounts(&payments),
+            vec![
+                (
+                    0,
+                    Account {
+                        total: Decimal::MAX,
+                        held: Decimal::MAX,
+                        is_locked: false,
+                        has_activity: true
+                    }
+                ),
+                (
+                    1,
+                    Account {
+                        total: Decimal::MAX,
+                        held: dec!(0),
+                        is_locked: false,
+                        has_activity: true
+                    }
+                ),
+                (
+                    2,
+                    Account {
+                        total: dec!(0),
+                        held: dec!(0),
+                        is_locked: false,
+                        has_activity: true
+                    }
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_client_stats() {
+        let mut payments = Payments::default();
+        let transaction = |id, kind| Transaction {
+            cid: 1,
+            tid: tid(id),
+            timestamp: None,
+            kind,
+        };
+        let transactions = [
+            transaction(1, TransactionKind::Deposit { amount: dec!(10) }),
+            transaction(2, TransactionKind::Deposit { amount: dec!(5) }),
+            transaction(3, TransactionKind::Withdrawal { amount: dec!(3) }),
+            transaction(4, TransactionKind::Withdrawal { amount: dec!(100) }),
+            transaction(
+                3,
+                TransactionKind::Refund {
+                    amount: Some(dec!(1)),
+                },
+            ),
+            transaction(1, TransactionKind::Dispute),
+            transaction(3, TransactionKind::Dispute),
+            transaction(1, TransactionKind::Resolve),
+            transaction(5, TransactionKind::Authorize { amount: dec!(1) }),
+            transaction(5, TransactionKind::Capture { amount: None }),
+            transaction(7, TransactionKind::Resolve),
+        ];
+        for transaction in &transactions {
+            let _ = payments.process_transaction(transaction);
+        }
+
+        assert_eq!(
+            payments.stats[1],
+            ClientStats {
+                deposits: 2,
+                withdrawals: 2,
+                deposited: dec!(15),
+                withdrawn: dec!(4),
+                open_disputes: 1,
+                disputed: dec!(2),
+                chargebacks: 0,
+                rejected: 2,
+                last_tid: Some(tid(7)),
+            }
+        );
+
+        let _ = payments.process_transaction(&transaction(3, TransactionKind::Chargeback));
+        let stats = payments.stats[1];
+        assert_eq!(
+            (stats.open_disputes, stats.disputed, stats.chargebacks),
+            (0, dec!(0), 1)
+        );
+    }
 }
@@ -0,0 +1,193 @@
+use std::collections::BTreeSet;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    ClientId,
+    output::Columns,
+    state::{SavedAccount, SavedState},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MismatchKind {
+    // Expected, but the engine has never seen the client
+    MissingClient,
+    // The engine has the client, but it was not expected
+    ExtraClient,
+    BalanceMismatch,
+    LockMismatch,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Mismatch {
+    pub client: ClientId,
+    pub kind: MismatchKind,
+    // The balance that does not match, for balance mismatches only
+    pub field: Option<&'static str>,
+    pub expected: String,
+    pub actual: String,
+    // Actual less expected, for balance mismatches only
+    pub difference: Option<Decimal>,
+}
+
+impl Columns for Mismatch {
+    const COLUMNS: &'static [&'static str] = &[
+        "client",
+        "kind",
+        "field",
+        "expected",
+        "actual",
+        "difference",
+    ];
+}
+
+fn balances(account: &SavedAccount) -> [(&'static str, Decimal); 3] {
+    [
+        ("available", account.available),
+        ("held", account.held),
+        ("total", account.total),
+    ]
+}
+
+// Compares what the engine came up with against what somebody else expects it to be, in
+// client order. Balances that are at most the tolerance apart are taken as matching
+pub fn reconcile(expected: &SavedState, actual: &SavedState, tolerance: Decimal) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+
+    let clients: BTreeSet<_> = expected
+        .accounts
+        .keys()
+        .chain(actual.accounts.keys())
+        .collect();
+    for &client in clients {
+        match (expected.accounts.get(&client), actual.accounts.get(&client)) {
+            (Some(_), None) => mismatches.push(Mismatch {
+                client,
+                kind: MismatchKind::MissingClient,
+                field: None,
+                expected: "present".to_string(),
+                actual: "absent".to_string(),
+                difference: None,
+            }),
+            (None, Some(_)) => mismatches.push(Mismatch {
+                client,
+                kind: MismatchKind::ExtraClient,
+                field: None,
+                expected: "absent".to_string(),
+                actual: "present".to_string(),
+                difference: None,
+            }),
+            (Some(expected), Some(actual)) => {
+                for ((field, expected), (_, actual)) in
+                    balances(expected).into_iter().zip(balances(actual))
+                {
+                    // Way too large a difference is way out of tolerance as well
+                    let difference = actual.checked_sub(expected);
+                    if difference.is_none_or(|difference| difference.abs() > tolerance) {
+                        mismatches.push(Mismatch {
+                            client,
+                            kind: MismatchKind::BalanceMismatch,
+                            field: Some(field),
+                            expected: expected.to_string(),
+                            actual: actual.to_string(),
+                            difference,
+                        });
+                    }
+                }
+                if expected.locked != actual.locked {
+                    mismatches.push(Mismatch {
+                        client,
+                        kind: MismatchKind::LockMismatch,
+                        field: None,
+                        expected: expected.locked.to_string(),
+                        actual: actual.locked.to_string(),
+                        difference: None,
+                    });
+                }
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use std::collections::BTreeMap;
+
+    fn account(available: Decimal, held: Decimal, locked: bool) -> SavedAccount {
+        SavedAccount {
+            available,
+            held,
+            total: available + held,
+            locked,
+        }
+    }
+
+    fn state(accounts: impl IntoIterator<Item = (ClientId, SavedAccount)>) -> SavedState {
+        SavedState {
+            accounts: accounts.into_iter().collect::<BTreeMap<_, _>>(),
+            actions: None,
+        }
+    }
+
+    #[test]
+    fn within_tolerance() {
+        let expected = state([(1, account(dec!(1.00), dec!(0), false))]);
+        let actual = state([(1, account(dec!(1.0001), dec!(0), false))]);
+
+        assert_eq!(reconcile(&expected, &actual, dec!(0.0001)), vec![]);
+        assert_eq!(reconcile(&expected, &actual, dec!(0)).len(), 2);
+    }
+
+    #[test]
+    fn mismatches_are_classified() {
+        let expected = state([
+            (1, account(dec!(5), dec!(0), false)),
+            (2, account(dec!(1), dec!(1), false)),
+            (3, account(dec!(0), dec!(0), false)),
+        ]);
+        let actual = state([
+            (2, account(dec!(1), dec!(1.5), true)),
+            (3, account(dec!(0), dec!(0), false)),
+            (4, account(dec!(9), dec!(0), false)),
+        ]);
+
+        let mismatches: Vec<_> = reconcile(&expected, &actual, dec!(0))
+            .into_iter()
+            .map(|mismatch| {
+                (
+                    mismatch.client,
+                    mismatch.kind,
+                    mismatch.field,
+                    mismatch.difference,
+                )
+            })
+            .collect();
+        assert_eq!(
+            mismatches,
+            vec![
+                (1, MismatchKind::MissingClient, None, None),
+                (
+                    2,
+                    MismatchKind::BalanceMismatch,
+                    Some("held"),
+                    Some(dec!(0.5))
+                ),
+                (
+                    2,
+                    MismatchKind::BalanceMismatch,
+                    Some("total"),
+                    Some(dec!(0.5))
+                ),
+                (2, MismatchKind::LockMismatch, None, None),
+                (4, MismatchKind::ExtraClient, None, None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use std::fmt;
+
+// Every reason for the engine to refuse a transaction. The spec wants us to carry on
+// after a bad transaction, so these are never fatal, but the caller might want to know
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    AccountLocked,
+    InsufficientFunds,
+    UnknownTransaction,
+    ForeignTransaction,
+    NotDisputable,
+    NotDisputed,
+    VelocityCountExceeded,
+    VelocityAmountExceeded,
+    MissingTimestamp,
+    NotAuthorization,
+    CaptureExceedsAuthorization,
+    NotRefundable,
+    RefundExceedsAmount,
+    Overflow,
+    // Turned down by the fraud rule with the given name
+    Rule(&'static str),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Rejection::AccountLocked => "account is locked",
+            Rejection::InsufficientFunds => "insufficient available funds",
+            Rejection::UnknownTransaction => "referenced transaction is unknown",
+            Rejection::ForeignTransaction => "referenced transaction belongs to another client",
+            Rejection::NotDisputable => "referenced transaction can not be disputed",
+            Rejection::NotDisputed => "referenced transaction is not under dispute",
+            Rejection::VelocityCountExceeded => "withdrawal count limit exceeded",
+            Rejection::VelocityAmountExceeded => "withdrawal amount limit exceeded",
+            Rejection::MissingTimestamp => "velocity limit requires a timestamp",
+            Rejection::NotAuthorization => "referenced transaction is not a pending authorization",
+            Rejection::CaptureExceedsAuthorization => "capture exceeds the authorized amount",
+            Rejection::NotRefundable => "referenced transaction can not be refunded",
+            Rejection::RefundExceedsAmount => "refund exceeds what is left of the transaction",
+            Rejection::Overflow => "amount overflows the balance",
+            Rejection::Rule(rule) => return write!(f, "rejected by the {rule} rule"),
+        };
+        f.write_str(reason)
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::{HashMap, VecDeque};
+
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+use crate::{
+    Account, Action, ActionKind, ClientId, Transaction, TransactionId, TransactionKind,
+    account::ClientStats,
+};
+
+// How many of the latest actions of every client we keep around for the rules to look at
+pub const RECENT_ACTIONS_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    // Nothing to see here
+    Accept,
+    // Let it through, but raise an alert
+    Flag(String),
+    // Refuse the transaction
+    Reject(String),
+    // Refuse the transaction and lock the account for investigation
+    Lock(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    pub tid: TransactionId,
+    pub cid: ClientId,
+    pub rule: &'static str,
+    pub message: String,
+}
+
+// Everything a rule is allowed to look at, before the transaction gets applied
+pub struct RuleContext<'a> {
+    pub transaction: &'a Transaction,
+    pub account: &'a Account,
+    pub stats: &'a ClientStats,
+    pub actions: &'a HashMap<TransactionId, Action>,
+    // Tids of the latest actions of the client, the most recent one goes last
+    pub recent_actions: Option<&'a VecDeque<TransactionId>>,
+}
+
+impl RuleContext<'_> {
+    // Latest actions of the client, the most recent one goes first. A tid reused by another
+    // client since is theirs now, so it is left out
+    pub fn recent(&self) -> impl Iterator<Item = &Action> {
+        self.recent_actions
+            .into_iter()
+            .flat_map(|tids| tids.iter().rev())
+            .filter_map(|tid| self.actions.get(tid))
+            .filter(|action| action.cid == self.transaction.cid)
+    }
+}
+
+// Shards run on threads of their own, rules included
+pub trait Rule: Send {
+    fn name(&self) -> &'static str;
+    fn evaluate(&self, context: &RuleContext) -> Verdict;
+}
+
+// Locks the account once too many of its transactions are on their way to a chargeback.
+// A chargeback locks the account on its own, so the ratio is judged as the disputes come
+// in, every one of those counting as a chargeback to be. Rules go before the engine, so
+// only disputes the engine is going to take count, not ones of unknown or foreign
+// transactions, nor of ones disputed already
+pub struct ChargebackRatio {
+    pub threshold: Decimal,
+    // Not judging clients with too little history, one chargeback out of two deposits means nothing
+    pub min_actions: u64,
+}
+
+impl Rule for ChargebackRatio {
+    fn name(&self) -> &'static str {
+        "chargeback-ratio"
+    }
+
+    fn evaluate(&self, context: &RuleContext) -> Verdict {
+        if !matches!(context.transaction.kind, TransactionKind::Dispute) {
+            return Verdict::Accept;
+        }
+        let disputable = context
+            .actions
+            .get(&context.transaction.tid)
+            .is_some_and(|action| action.cid == context.transaction.cid && action.is_disputable());
+        if !disputable {
+            return Verdict::Accept;
+        }
+        let actions = context.stats.deposits + context.stats.withdrawals;
+        if actions < self.min_actions || actions == 0 {
+            return Verdict::Accept;
+        }
+        let chargebacks = context.stats.chargebacks + context.stats.open_disputes + 1;
+        let ratio = Decimal::from(chargebacks) / Decimal::from(actions);
+        if ratio > self.threshold {
+            return Verdict::Lock(format!(
+                "chargeback ratio {} is above {}",
+                ratio.round_dp(4),
+                self.threshold
+            ));
+        }
+        Verdict::Accept
+    }
+}
+
+// Flags a withdrawal that drains the account right after the client has made a deposit. One
+// for more than there is gets refused by the engine anyway, it drains nothing
+pub struct DepositThenWithdrawal;
+
+impl Rule for DepositThenWithdrawal {
+    fn name(&self) -> &'static str {
+        "deposit-then-withdrawal"
+    }
+
+    fn evaluate(&self, context: &RuleContext) -> Verdict {
+        let TransactionKind::Withdrawal { amount } = context.transaction.kind else {
+            return Verdict::Accept;
+        };
+        match context.recent().next() {
+            Some(Action {
+                kind: ActionKind::Deposit { amount: deposited },
+                ..
+            }) if amount == context.account.get_available() => Verdict::Flag(format!(
+                "withdrawal of {amount} empties the account right after a deposit of {deposited}"
+            )),
+            _ => Verdict::Accept,
+        }
+    }
+}
+
+// Catches attempts to dispute, resolve or charge back a transaction of some other client.
+// The engine refuses those anyway, but someone trying it is worth an alert
+pub struct CrossClientDispute;
+
+impl Rule for CrossClientDispute {
+    fn name(&self) -> &'static str {
+        "cross-client-dispute"
+    }
+
+    fn evaluate(&self, context: &RuleContext) -> Verdict {
+        if !matches!(
+            context.transaction.kind,
+            TransactionKind::Dispute | TransactionKind::Resolve | TransactionKind::Chargeback
+        ) {
+            return Verdict::Accept;
+        }
+        match context.actions.get(&context.transaction.tid) {
+            Some(action) if action.cid != context.transaction.cid => Verdict::Flag(format!(
+                "client {} referenced transaction {} of client {}",
+                context.transaction.cid, context.transaction.tid, action.cid
+            )),
+            _ => Verdict::Accept,
+        }
+    }
+}
+
+pub fn default_rules() -> Vec<Box<dyn Rule>> {
+    vec![
+        Box::new(ChargebackRatio {
+            threshold: dec!(0.1),
+            min_actions: 10,
+        }),
+        Box::new(DepositThenWithdrawal),
+        Box::new(CrossClientDispute),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{id::tid, transaction::ActionStatus};
+
+    fn deposit(cid: ClientId, amount: Decimal) -> Action {
+        Action {
+            cid,
+            kind: ActionKind::Deposit { amount },
+            status: ActionStatus::Fresh,
+            refunded: Decimal::ZERO,
+        }
+    }
+
+    #[test]
+    fn chargeback_ratio_locks_above_threshold() {
+        let rule = ChargebackRatio {
+            threshold: dec!(0.1),
+            min_actions: 10,
+        };
+        let dispute = |cid, id| Transaction {
+            cid,
+            tid: tid(id),
+            timestamp: None,
+            kind: TransactionKind::Dispute,
+        };
+        let transaction = dispute(0, 0);
+        let actions = HashMap::from([(tid(0), deposit(0, dec!(1)))]);
+        let mut context = RuleContext {
+            transaction: &transaction,
+            account: &Account::default(),
+            stats: &ClientStats {
+                deposits: 10,
+                withdrawals: 0,
+                ..Default::default()
+            },
+            actions: &actions,
+            recent_actions: None,
+        };
+        // The dispute itself makes it 1 out of 10
+        assert_eq!(rule.evaluate(&context), Verdict::Accept);
+
+        let stats = ClientStats {
+            deposits: 9,
+            withdrawals: 1,
+            open_disputes: 1,
+            ..Default::default()
+        };
+        context.stats = &stats;
+        assert!(matches!(rule.evaluate(&context), Verdict::Lock(_)));
+
+        // Disputes the engine refuses on its own do not count
+        let unknown = dispute(0, 999);
+        context.transaction = &unknown;
+        assert_eq!(rule.evaluate(&context), Verdict::Accept);
+        let foreign = dispute(1, 0);
+        context.transaction = &foreign;
+        assert_eq!(rule.evaluate(&context), Verdict::Accept);
+        context.transaction = &transaction;
+        let disputed = HashMap::from([(
+            tid(0),
+            Action {
+                status: ActionStatus::Disputed,
+                ..deposit(0, dec!(1))
+            },
+        )]);
+        context.actions = &disputed;
+        assert_eq!(rule.evaluate(&context), Verdict::Accept);
+        context.actions = &actions;
+
+        // Only disputes are judged
+        let deposit = Transaction {
+            kind: TransactionKind::Deposit { amount: dec!(1) },
+            ..transaction
+        };
+        context.transaction = &deposit;
+        assert_eq!(rule.evaluate(&context), Verdict::Accept);
+        context.transaction = &transaction;
+
+        // Too little history to judge
+        let stats = ClientStats {
+            deposits: 1,
+            withdrawals: 0,
+            chargebacks: 1,
+            ..Default::default()
+        };
+        context.stats = &stats;
+        assert_eq!(rule.evaluate(&context), Verdict::Accept);
+    }
+
+    #[test]
+    fn deposit_then_draining_withdrawal_is_flagged() {
+        let actions = HashMap::from([(tid(0), deposit(0, dec!(100)))]);
+        let recent = VecDeque::from([tid(0)]);
+        let account = Account {
+            total: dec!(100),
+            held: dec!(0),
+            is_locked: false,
+            has_activity: true,
+        };
+        let withdrawal = |amount| Transaction {
+            cid: 0,
+            tid: tid(1),
+            timestamp: None,
+            kind: TransactionKind::Withdrawal { amount },
+        };
+
+        let full = withdrawal(dec!(100));
+        let context = RuleContext {
+            transaction: &full,
+            account: &account,
+            stats: &ClientStats::default(),
+            actions: &actions,
+            recent_actions: Some(&recent),
+        };
+        assert!(matches!(
+            DepositThenWithdrawal.evaluate(&context),
+            Verdict::Flag(_)
+        ));
+
+        let partial = withdrawal(dec!(60));
+        let context = RuleContext {
+            transaction: &partial,
+            ..context
+        };
+        assert_eq!(DepositThenWithdrawal.evaluate(&context), Verdict::Accept);
+
+        // Refused by the engine for the lack of funds, it drains nothing
+        let overdraft = withdrawal(dec!(500));
+        let context = RuleContext {
+            transaction: &overdraft,
+            ..context
+        };
+        assert_eq!(DepositThenWithdrawal.evaluate(&context), Verdict::Accept);
+
+        // The tid of the deposit has been reused by another client since
+        let reused = HashMap::from([(tid(0), deposit(1, dec!(100)))]);
+        let context = RuleContext {
+            transaction: &full,
+            actions: &reused,
+            ..context
+        };
+        assert_eq!(DepositThenWithdrawal.evaluate(&context), Verdict::Accept);
+    }
+
+    #[test]
+    fn cross_client_dispute_is_flagged() {
+        let actions = HashMap::from([(tid(0), deposit(0, dec!(100)))]);
+        let dispute = |cid| Transaction {
+            cid,
+            tid: tid(0),
+            timestamp: None,
+            kind: TransactionKind::Dispute,
+        };
+
+        let foreign = dispute(1);
+        let context = RuleContext {
+            transaction: &foreign,
+            account: &Account::default(),
+            stats: &ClientStats::default(),
+            actions: &actions,
+            recent_actions: None,
+        };
+        assert!(matches!(
+            CrossClientDispute.evaluate(&context),
+            Verdict::Flag(_)
+        ));
+
+        let own = dispute(0);
+        let context = RuleContext {
+            transaction: &own,
+            ..context
+        };
+        assert_eq!(CrossClientDispute.evaluate(&context), Verdict::Accept);
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{ClientId, payments::Payments};
+
+// Disputes and the like may only refer to transactions of the same client, so as long as
+// every transaction of a client goes to the same shard, in order, the shards never need
+// to know about each other
+pub fn shard_of(cid: ClientId, shards: usize) -> usize {
+    // More shards than there could be clients leaves every client a shard of its own
+    ClientId::try_from(shards).map_or(cid, |shards| cid % shards) as usize
+}
+
+// Puts the shards back together into one engine, as if it had seen the whole input. The
+// shards have to be in order, the one at index i is the one shard_of points to with i
+pub fn merge(shards: Vec<Payments>) -> Payments {
+    let count = shards.len();
+    let mut merged = Payments::default();
+    for (index, shard) in shards.into_iter().enumerate() {
+        // A shard may have a slot for every client, only its own are worth taking
+        for (cid, account) in shard.accounts.iter() {
+            if shard_of(cid, count) == index {
+                merged.accounts[cid] = *account;
+            }
+        }
+        for (cid, stats) in shard.stats.iter() {
+            if shard_of(cid, count) == index {
+                merged.stats[cid] = *stats;
+            }
+        }
+        // Whoever feeds the shards has to make sure no tid is used by the clients of two
+        // of them, or which one comes out here would depend on the order of the shards
+        merged.actions.extend(shard.actions);
+        merged.limits.extend(shard.limits);
+        merged.withdrawal_windows.extend(shard.withdrawal_windows);
+        merged.recent_actions.extend(shard.recent_actions);
+        merged.rows_processed = merged.rows_processed.max(shard.rows_processed);
+        merged.ledger.merge(shard.ledger);
+        merged.violations.extend(shard.violations);
+        if let Some(statement) = shard.statement
+            && shard_of(statement.client, count) == index
+        {
+            merged.statement = Some(statement);
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Transaction,
+        id::tid,
+        input::{self, CsvDialect},
+        ledger::LedgerAccount,
+    };
+    use rust_decimal::Decimal;
+    use std::io;
+
+    fn transactions(input: &str) -> Vec<Transaction> {
+        input::read_csv(io::Cursor::new(input.to_string()), &CsvDialect::default())
+            .unwrap()
+            .map(|(_, result)| result.unwrap())
+            .collect()
+    }
+
+    // The input run on one engine, and on two shards merged back together
+    fn single_and_sharded(input: &str) -> (Payments, Payments) {
+        let mut single = Payments::default();
+        for transaction in transactions(input) {
+            let _ = single.process_transaction(&transaction);
+        }
+
+        let mut shards: Vec<_> = (0..2).map(|_| Payments::default()).collect();
+        for (row, transaction) in transactions(input).into_iter().enumerate() {
+            let shard = &mut shards[shard_of(transaction.cid, 2)];
+            shard.rows_processed = row as u64;
+            let _ = shard.process_transaction(&transaction);
+        }
+        (single, merge(shards))
+    }
+
+    #[test]
+    fn sharded_is_the_same_as_single() {
+        let (single, merged) = single_and_sharded(
+            "type,client,tx,amount\n\
+             deposit,1,1,5\n\
+             deposit,2,2,3\n\
+             withdrawal,1,3,1\n\
+             dispute,2,2,\n\
+             deposit,3,4,2.5\n\
+             dispute,1,1,\n\
+             chargeback,1,1,\n\
+             withdrawal,3,5,1\n\
+             dispute,3,2,\n\
+             resolve,2,2,\n",
+        );
+
+        assert_eq!(merged.accounts, single.accounts);
+        assert_eq!(merged.stats, single.stats);
+        assert_eq!(merged.rows_processed, single.rows_processed);
+        assert_eq!(
+            merged.ledger.trial_balance().collect::<Vec<_>>(),
+            single.ledger.trial_balance().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            merged.ledger.balance(LedgerAccount::House),
+            single.ledger.balance(LedgerAccount::House)
+        );
+        let mut tids: Vec<_> = merged.actions.keys().collect();
+        tids.sort();
+        assert_eq!(tids, [1, 2, 3, 4, 5].map(tid).iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn sharded_overflowing_is_the_same_as_single() {
+        // More money than a Decimal holds goes through the house, in and out, and the
+        // clients end up owed more than that too
+        let amount = "9999999999999999999999999999";
+        let mut input = "type,client,tx,amount\n".to_string();
+        for round in 0..7 {
+            input += &format!("deposit,1,{},{amount}\n", 2 * round + 1);
+            input += &format!("withdrawal,1,{},{amount}\n", 2 * round + 2);
+        }
+        for cid in 1..=9 {
+            input += &format!("deposit,{cid},{},{amount}\n", 20 + cid);
+        }
+        let (single, merged) = single_and_sharded(&input);
+
+        assert_eq!(merged.accounts, single.accounts);
+        assert_eq!(merged.stats, single.stats);
+        assert_eq!(
+            merged.ledger.trial_balance().collect::<Vec<_>>(),
+            single.ledger.trial_balance().collect::<Vec<_>>()
+        );
+        for cid in 1..=9 {
+            assert_eq!(merged.accounts[cid].total.to_string(), amount);
+        }
+        assert_eq!(merged.ledger.balance(LedgerAccount::House), -Decimal::MAX);
+    }
+}
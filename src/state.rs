@@ -0,0 +1,182 @@
+use std::{collections::BTreeMap, fs::File, path::Path};
+
+use anyhow::Context;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{ActionKind, ClientId, TransactionId, payments::Payments, transaction::ActionStatus};
+
+// What's left of a run, enough to tell what another run did differently. Unlike the output
+// it keeps the balances at full precision, and the transactions the engine remembers
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedState {
+    pub accounts: BTreeMap<ClientId, SavedAccount>,
+    // Outputs know nothing of the actions, hence the option
+    pub actions: Option<BTreeMap<TransactionId, SavedAction>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedAccount {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedAction {
+    pub client: ClientId,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub amount: Decimal,
+    pub refunded: Decimal,
+    pub status: ActionStatus,
+}
+
+impl From<&Payments> for SavedState {
+    fn from(payments: &Payments) -> Self {
+        let accounts = payments
+            .accounts
+            .iter()
+            .filter(|(_, account)| account.has_activity)
+            .map(|(client, account)| {
+                (
+                    client,
+                    SavedAccount {
+                        available: account.get_available(),
+                        held: account.held,
+                        total: account.total,
+                        locked: account.is_locked,
+                    },
+                )
+            })
+            .collect();
+        let actions = payments
+            .actions
+            .iter()
+            .map(|(tid, action)| {
+                let (ActionKind::Deposit { amount }
+                | ActionKind::Withdrawal { amount }
+                | ActionKind::Authorization { amount }) = action.kind;
+                (
+                    *tid,
+                    SavedAction {
+                        client: action.cid,
+                        kind: action.kind.name().to_string(),
+                        amount,
+                        refunded: action.refunded,
+                        status: action.status.clone(),
+                    },
+                )
+            })
+            .collect();
+
+        SavedState {
+            accounts,
+            actions: Some(actions),
+        }
+    }
+}
+
+pub fn save_state(payments: &Payments, path: &Path) -> anyhow::Result<()> {
+    let file = std::io::BufWriter::new(File::create(path)?);
+    serde_json::to_writer(file, &SavedState::from(payments))?;
+    Ok(())
+}
+
+// Takes either a state saved by a run, or the csv output of one. The latter is only as
+// precise as it was printed, and has no actions in it
+pub fn load_state(path: &Path) -> anyhow::Result<SavedState> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    parse_state(&content)
+}
+
+fn parse_state(content: &str) -> anyhow::Result<SavedState> {
+    #[derive(Deserialize)]
+    struct OutputCSVRow {
+        client: ClientId,
+        available: Decimal,
+        held: Decimal,
+        total: Decimal,
+        locked: bool,
+    }
+
+    if content.trim_start().starts_with('{') {
+        return Ok(serde_json::from_str(content)?);
+    }
+
+    let mut state = SavedState::default();
+    for result in csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes())
+        .deserialize::<OutputCSVRow>()
+    {
+        let row = result?;
+        let account = SavedAccount {
+            available: row.available,
+            held: row.held,
+            total: row.total,
+            locked: row.locked,
+        };
+        if state.accounts.insert(row.client, account).is_some() {
+            anyhow::bail!("client {} is in the output more than once", row.client);
+        }
+    }
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Transaction, TransactionKind, id::tid};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn saved_state_round_trip() {
+        let mut payments = Payments::default();
+        for (id, kind) in [
+            (1, TransactionKind::Deposit { amount: dec!(1.25) }),
+            (1, TransactionKind::Dispute),
+        ] {
+            let _ = payments.process_transaction(&Transaction {
+                cid: 4,
+                tid: tid(id),
+                timestamp: None,
+                kind,
+            });
+        }
+
+        let state = SavedState::from(&payments);
+        assert_eq!(
+            state.actions.as_ref().unwrap()[&tid(1)].status,
+            ActionStatus::Disputed
+        );
+        let saved = serde_json::to_string(&state).unwrap();
+        assert_eq!(parse_state(&saved).unwrap(), state);
+    }
+
+    #[test]
+    fn output_as_state() {
+        let state = parse_state(
+            "client,available,held,total,locked,deposits\n\
+             1,1.5000,0.0000,1.5000,false,2\n",
+        )
+        .unwrap();
+
+        assert_eq!(state.actions, None);
+        assert_eq!(
+            state.accounts[&1],
+            SavedAccount {
+                available: dec!(1.5),
+                held: dec!(0),
+                total: dec!(1.5),
+                locked: false,
+            }
+        );
+        assert!(
+            parse_state("client,available,held,total,locked\n1,1,0,1,false\n1,1,0,1,false")
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    Account, ClientId, Transaction, TransactionId,
+    output::{Columns, DecimalFormat},
+};
+
+// Everything that happened to a single client, line by line. The engine keeps only the
+// final state of the accounts, so the history has to be written down as it goes
+#[derive(Debug)]
+pub struct Statement {
+    pub client: ClientId,
+    pub lines: Vec<StatementLine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementLine {
+    pub tid: TransactionId,
+    pub kind: &'static str,
+    // Why the transaction did not go through, if it didn't
+    pub rejection: Option<String>,
+    pub before: Account,
+    pub after: Account,
+}
+
+impl Statement {
+    pub fn new(client: ClientId) -> Self {
+        Statement {
+            client,
+            lines: Vec::new(),
+        }
+    }
+
+    // Transactions of other clients are of no interest, they can't touch this one
+    pub fn record(
+        &mut self,
+        transaction: &Transaction,
+        before: Account,
+        after: Account,
+        rejection: Option<String>,
+    ) {
+        if transaction.cid != self.client {
+            return;
+        }
+        self.lines.push(StatementLine {
+            tid: transaction.tid,
+            kind: transaction.kind.name(),
+            rejection,
+            before,
+            after,
+        });
+    }
+}
+
+// One line of the statement as it goes out: what the transaction did to each balance,
+// and the balances right after it. The last line ends up where the summary does
+#[derive(Debug, Serialize)]
+pub struct StatementRow {
+    pub tx: TransactionId,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub status: &'static str,
+    pub reason: String,
+    pub available_change: String,
+    pub held_change: String,
+    pub total_change: String,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: bool,
+}
+
+impl Columns for StatementRow {
+    const COLUMNS: &'static [&'static str] = &[
+        "tx",
+        "type",
+        "status",
+        "reason",
+        "available_change",
+        "held_change",
+        "total_change",
+        "available",
+        "held",
+        "total",
+        "locked",
+    ];
+}
+
+impl StatementRow {
+    pub fn new(line: &StatementLine, format: &DecimalFormat) -> Self {
+        // Can't overflow, every change is an amount that has been posted already
+        let change = |before: Decimal, after: Decimal| format.format(after - before);
+        StatementRow {
+            tx: line.tid,
+            kind: line.kind,
+            status: if line.rejection.is_some() {
+                "rejected"
+            } else {
+                "applied"
+            },
+            reason: line.rejection.clone().unwrap_or_default(),
+            available_change: change(line.before.get_available(), line.after.get_available()),
+            held_change: change(line.before.held, line.after.held),
+            total_change: change(line.before.total, line.after.total),
+            available: format.format(line.after.get_available()),
+            held: format.format(line.after.held),
+            total: format.format(line.after.total),
+            locked: line.after.is_locked,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TransactionKind, id::tid, payments::Payments};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn statement_follows_the_client() {
+        let mut payments = Payments {
+            statement: Some(Statement::new(1)),
+            ..Default::default()
+        };
+        let transactions = [
+            (1, 1, TransactionKind::Deposit { amount: dec!(10) }),
+            (2, 2, TransactionKind::Deposit { amount: dec!(7) }),
+            (1, 3, TransactionKind::Withdrawal { amount: dec!(20) }),
+            (1, 1, TransactionKind::Dispute),
+            (1, 1, TransactionKind::Chargeback),
+        ];
+        for (cid, id, kind) in transactions {
+            let _ = payments.process_transaction(&Transaction {
+                cid,
+                tid: tid(id),
+                timestamp: None,
+                kind,
+            });
+        }
+
+        let format = DecimalFormat::default();
+        let rows: Vec<_> = payments
+            .statement
+            .as_ref()
+            .unwrap()
+            .lines
+            .iter()
+            .map(|line| StatementRow::new(line, &format))
+            .map(|row| {
+                format!(
+                    "{} {} {} {} {} {} {} {} {}",
+                    row.tx,
+                    row.kind,
+                    row.status,
+                    row.available_change,
+                    row.held_change,
+                    row.total_change,
+                    row.available,
+                    row.held,
+                    row.total
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            rows,
+            vec![
+                "1 deposit applied 10.0000 0.0000 10.0000 10.0000 0.0000 10.0000",
+                "3 withdrawal rejected 0.0000 0.0000 0.0000 10.0000 0.0000 10.0000",
+                "1 dispute applied -10.0000 10.0000 0.0000 0.0000 10.0000 10.0000",
+                "1 chargeback applied 10.0000 -10.0000 0.0000 10.0000 0.0000 10.0000",
+            ]
+        );
+        let lines = &payments.statement.as_ref().unwrap().lines;
+        assert_eq!(
+            lines[1].rejection.as_deref(),
+            Some("insufficient available funds")
+        );
+        assert_eq!(lines.last().unwrap().after, payments.accounts[1]);
+    }
+}
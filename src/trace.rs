@@ -0,0 +1,262 @@
+use std::{fmt, io::Write};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    Account, ClientId, Transaction, TransactionId, TransactionKind, payments::Payments,
+    transaction::ActionStatus,
+};
+
+// Writes down what every input row did to the engine, one json object per row, so that
+// traces of the same input could be diffed between versions
+pub struct Tracer {
+    writer: Box<dyn Write>,
+}
+
+// The state the transaction is about to change, taken right before it is applied
+#[derive(Debug, Clone)]
+pub struct TraceSnapshot {
+    account: Account,
+    branch: String,
+    status: Option<ActionStatus>,
+}
+
+#[derive(Serialize)]
+struct TraceTransaction {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    client: ClientId,
+    tx: TransactionId,
+    amount: Option<Decimal>,
+    timestamp: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct TraceAccount {
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+impl From<&Account> for TraceAccount {
+    fn from(account: &Account) -> Self {
+        TraceAccount {
+            available: account.get_available(),
+            held: account.held,
+            total: account.total,
+            locked: account.is_locked,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum TraceOutcome {
+    Applied,
+    Rejected,
+    Unparsed,
+}
+
+#[derive(Serialize)]
+struct TraceRecord {
+    // The line of the input the row starts on, the same one issues and errors are reported at
+    row: u64,
+    outcome: TraceOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transaction: Option<TraceTransaction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    before: Option<TraceAccount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after: Option<TraceAccount>,
+    // Of the transaction stored under the tid, if there is one
+    status_before: Option<ActionStatus>,
+    status_after: Option<ActionStatus>,
+}
+
+// The branch process_transaction is going to take, judging by the kind of the transaction
+// and of whatever it refers to
+fn branch(payments: &Payments, transaction: &Transaction) -> String {
+    match transaction.kind {
+        TransactionKind::Deposit { .. }
+        | TransactionKind::Withdrawal { .. }
+        | TransactionKind::Authorize { .. } => transaction.kind.name().to_string(),
+        _ => match payments.actions.get(&transaction.tid) {
+            Some(action) if action.cid != transaction.cid => {
+                format!("{} of foreign transaction", transaction.kind.name())
+            }
+            Some(action) => format!("{} of {}", transaction.kind.name(), action.kind.name()),
+            None => format!("{} of unknown transaction", transaction.kind.name()),
+        },
+    }
+}
+
+impl Tracer {
+    pub fn new(writer: Box<dyn Write>) -> Self {
+        Tracer { writer }
+    }
+
+    pub fn snapshot(&self, payments: &Payments, transaction: &Transaction) -> TraceSnapshot {
+        TraceSnapshot {
+            account: payments.accounts[transaction.cid],
+            branch: branch(payments, transaction),
+            status: payments
+                .actions
+                .get(&transaction.tid)
+                .map(|action| action.status.clone()),
+        }
+    }
+
+    // Should be called once the transaction went through, or didn't
+    pub fn record(
+        &mut self,
+        line: u64,
+        payments: &Payments,
+        transaction: &Transaction,
+        before: TraceSnapshot,
+        rejection: Option<&dyn fmt::Display>,
+    ) -> anyhow::Result<()> {
+        let record = TraceRecord {
+            row: line,
+            outcome: match rejection {
+                Some(_) => TraceOutcome::Rejected,
+                None => TraceOutcome::Applied,
+            },
+            error: rejection.map(|rejection| rejection.to_string()),
+            transaction: Some(TraceTransaction {
+                kind: transaction.kind.name(),
+                client: transaction.cid,
+                tx: transaction.tid,
+                amount: transaction.amount(),
+                timestamp: transaction.timestamp,
+            }),
+            branch: Some(before.branch),
+            before: Some((&before.account).into()),
+            after: Some((&payments.accounts[transaction.cid]).into()),
+            status_before: before.status,
+            status_after: payments
+                .actions
+                .get(&transaction.tid)
+                .map(|action| action.status.clone()),
+        };
+        self.write(&record)
+    }
+
+    // Rows that could not be parsed are written down as well, with the parse error only
+    pub fn record_unparsed(&mut self, line: u64, error: &dyn fmt::Display) -> anyhow::Result<()> {
+        let record = TraceRecord {
+            row: line,
+            outcome: TraceOutcome::Unparsed,
+            error: Some(error.to_string()),
+            transaction: None,
+            branch: None,
+            before: None,
+            after: None,
+            status_before: None,
+            status_after: None,
+        };
+        self.write(&record)
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    fn write(&mut self, record: &TraceRecord) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        writeln!(self.writer)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::id::tid;
+    use rust_decimal_macros::dec;
+    use std::{cell::RefCell, io, rc::Rc};
+
+    // Lets the test read back what the tracer has written
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trace_lines() {
+        let buffer = SharedBuffer::default();
+        let mut tracer = Tracer::new(Box::new(buffer.clone()));
+        let mut payments = Payments::default();
+        // The rows are traced at the lines of the input they start on, blank lines in between
+        let transactions = [
+            (2, 1, TransactionKind::Withdrawal { amount: dec!(1) }),
+            (3, 1, TransactionKind::Dispute),
+            (5, 2, TransactionKind::Deposit { amount: dec!(2.5) }),
+        ];
+        for (line, id, kind) in transactions {
+            let transaction = Transaction {
+                cid: 3,
+                tid: tid(id),
+                timestamp: None,
+                kind,
+            };
+            let before = tracer.snapshot(&payments, &transaction);
+            let result = payments.process_transaction(&transaction);
+            let rejection = result.as_ref().err().map(|r| r as &dyn fmt::Display);
+            tracer
+                .record(line, &payments, &transaction, before, rejection)
+                .unwrap();
+            if id == 2 {
+                tracer
+                    .record_unparsed(6, &"unknown transaction type: magic")
+                    .unwrap();
+            }
+        }
+        let _ = payments.process_transaction(&Transaction {
+            cid: 3,
+            tid: tid(2),
+            timestamp: None,
+            kind: TransactionKind::Dispute,
+        });
+        let transaction = Transaction {
+            cid: 3,
+            tid: tid(2),
+            timestamp: None,
+            kind: TransactionKind::Resolve,
+        };
+        let before = tracer.snapshot(&payments, &transaction);
+        payments.process_transaction(&transaction).unwrap();
+        tracer
+            .record(9, &payments, &transaction, before, None)
+            .unwrap();
+
+        let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        // The tx field is a number or a string, as the id type has it
+        let tx = |id| format!("\"tx\":{}", serde_json::to_string(&tid(id)).unwrap());
+        assert_eq!(
+            lines,
+            [
+                r#"{"row":2,"outcome":"rejected","error":"insufficient available funds","transaction":{"type":"withdrawal","client":3,"tx":1,"amount":"1","timestamp":null},"branch":"withdrawal","before":{"available":"0","held":"0","total":"0","locked":false},"after":{"available":"0","held":"0","total":"0","locked":false},"status_before":null,"status_after":null}"#,
+                r#"{"row":3,"outcome":"rejected","error":"referenced transaction is unknown","transaction":{"type":"dispute","client":3,"tx":1,"amount":null,"timestamp":null},"branch":"dispute of unknown transaction","before":{"available":"0","held":"0","total":"0","locked":false},"after":{"available":"0","held":"0","total":"0","locked":false},"status_before":null,"status_after":null}"#,
+                r#"{"row":5,"outcome":"applied","transaction":{"type":"deposit","client":3,"tx":2,"amount":"2.5","timestamp":null},"branch":"deposit","before":{"available":"0","held":"0","total":"0","locked":false},"after":{"available":"2.5","held":"0","total":"2.5","locked":false},"status_before":null,"status_after":"fresh"}"#,
+                r#"{"row":6,"outcome":"unparsed","error":"unknown transaction type: magic","status_before":null,"status_after":null}"#,
+                r#"{"row":9,"outcome":"applied","transaction":{"type":"resolve","client":3,"tx":2,"amount":null,"timestamp":null},"branch":"resolve of deposit","before":{"available":"0.0","held":"2.5","total":"2.5","locked":false},"after":{"available":"0.0","held":"0.0","total":"0.0","locked":false},"status_before":"disputed","status_after":"reverted"}"#,
+            ]
+            .map(|line| line.replace("\"tx\":1", &tx(1)).replace("\"tx\":2", &tx(2)))
+        );
+    }
+}
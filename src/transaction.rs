@@ -1,19 +1,63 @@
-use rust_decimal::Decimal;
-use serde::{Deserialize, de::Deserializer};
+use std::{borrow::Cow, fmt};
 
-#[derive(Debug)]
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize, de::Deserializer};
+
+use crate::{ClientId, TransactionId};
+
+#[derive(Debug, PartialEq)]
 pub enum TransactionKind {
     Deposit { amount: Decimal },
     Withdrawal { amount: Decimal },
     Dispute,
     Resolve,
     Chargeback,
+    // Reserves funds for a later capture
+    Authorize { amount: Decimal },
+    // Settles an authorization, either whole (no amount) or just a part of it
+    Capture { amount: Option<Decimal> },
+    // Releases an authorization without settling anything
+    Void,
+    // Voluntarily gives back a deposit or a withdrawal, either whole (no amount) or a part of it
+    Refund { amount: Option<Decimal> },
+    // Takes back whatever is left of a deposit or a withdrawal, at the processor's request
+    Reversal,
+}
+
+impl TransactionKind {
+    // Whether the transaction brings a tid of its own, rather than referring to an earlier one
+    pub fn creates_tid(&self) -> bool {
+        matches!(
+            self,
+            TransactionKind::Deposit { .. }
+                | TransactionKind::Withdrawal { .. }
+                | TransactionKind::Authorize { .. }
+        )
+    }
+
+    // As the type column of the input has it
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionKind::Deposit { .. } => "deposit",
+            TransactionKind::Withdrawal { .. } => "withdrawal",
+            TransactionKind::Dispute => "dispute",
+            TransactionKind::Resolve => "resolve",
+            TransactionKind::Chargeback => "chargeback",
+            TransactionKind::Authorize { .. } => "authorize",
+            TransactionKind::Capture { .. } => "capture",
+            TransactionKind::Void => "void",
+            TransactionKind::Refund { .. } => "refund",
+            TransactionKind::Reversal => "reversal",
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Transaction {
-    pub tid: u32,
-    pub cid: u16,
+    pub tid: TransactionId,
+    pub cid: ClientId,
+    // Optional unix time of the transaction, only time based velocity limits care about it
+    pub timestamp: Option<u64>,
     pub kind: TransactionKind,
 }
 
@@ -21,105 +65,402 @@ pub struct Transaction {
 pub enum ActionKind {
     Deposit { amount: Decimal },
     Withdrawal { amount: Decimal },
+    Authorization { amount: Decimal },
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ActionStatus {
     // All actions are born with status == fresh
     Fresh,
     // If the client ever tries to dispute the transaction
     // it becomes status == disputed
     Disputed,
-    // After being resolved or charged back
+    // After being charged back, voided, reversed or refunded in full
     // it becomes status == final
     Final,
+    // After being resolved, the transaction is undone as if it never happened
+    // and it becomes status == reverted
+    Reverted,
 }
 
 #[derive(Debug, Clone)]
 pub struct Action {
-    pub cid: u16,
+    pub cid: ClientId,
     pub kind: ActionKind,
     pub status: ActionStatus,
+    // How much of the amount has been refunded so far, disputes only care about the rest
+    pub refunded: Decimal,
 }
 
-impl<'de> Deserialize<'de> for Transaction {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        #[derive(Deserialize)]
-        struct TransactionCSVRow<'a> {
-            #[serde(rename = "type")]
-            pub kind: &'a str,
-
-            #[serde(rename = "client")]
-            pub cid: u16,
+impl ActionKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ActionKind::Deposit { .. } => "deposit",
+            ActionKind::Withdrawal { .. } => "withdrawal",
+            ActionKind::Authorization { .. } => "authorization",
+        }
+    }
+}
 
-            #[serde(rename = "tx")]
-            pub tid: u32,
+impl ActionStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ActionStatus::Fresh => "fresh",
+            ActionStatus::Disputed => "disputed",
+            ActionStatus::Final => "final",
+            ActionStatus::Reverted => "reverted",
+        }
+    }
+}
 
-            pub amount: Option<Decimal>,
+impl Action {
+    // Whatever is left of the amount after refunds, that is what a dispute is about
+    pub fn remaining(&self) -> Decimal {
+        match self.kind {
+            ActionKind::Deposit { amount } | ActionKind::Withdrawal { amount } => {
+                amount - self.refunded
+            }
+            ActionKind::Authorization { amount } => amount,
         }
+    }
 
-        let row = TransactionCSVRow::deserialize(deserializer)?;
-        let kind = match row.kind {
+    // Not disputed or final yet, and pending authorizations have nothing settled to dispute
+    pub fn is_disputable(&self) -> bool {
+        self.status == ActionStatus::Fresh && !matches!(self.kind, ActionKind::Authorization { .. })
+    }
+}
+
+impl Transaction {
+    // Everything a transaction has to be, no matter where it comes from. The error is the
+    // message for the deserializer to report
+    pub fn from_fields(
+        kind: &str,
+        cid: ClientId,
+        tid: TransactionId,
+        amount: Option<Decimal>,
+        timestamp: Option<u64>,
+    ) -> Result<Self, String> {
+        let kind = match kind {
             "deposit" => {
-                let amount = row
-                    .amount
-                    .ok_or_else(|| serde::de::Error::custom("deposit requires amount"))?;
+                let amount = amount.ok_or_else(|| format!("deposit {MISSING_AMOUNT}"))?;
                 if amount <= Decimal::ZERO {
-                    return Err(serde::de::Error::custom("deposit amount must be positive"));
+                    return Err(format!("deposit {NON_POSITIVE_AMOUNT}"));
                 }
                 TransactionKind::Deposit { amount }
             }
             "withdrawal" => {
-                let amount = row
-                    .amount
-                    .ok_or_else(|| serde::de::Error::custom("withdrawal requires amount"))?;
+                let amount = amount.ok_or_else(|| format!("withdrawal {MISSING_AMOUNT}"))?;
                 if amount <= Decimal::ZERO {
-                    return Err(serde::de::Error::custom(
-                        "withdrawal amount must be positive",
-                    ));
+                    return Err(format!("withdrawal {NON_POSITIVE_AMOUNT}"));
                 }
                 TransactionKind::Withdrawal { amount }
             }
             "dispute" => {
-                if row.amount.is_some() {
-                    return Err(serde::de::Error::custom("dispute must not have amount"));
+                if amount.is_some() {
+                    return Err(format!("dispute {UNEXPECTED_AMOUNT}"));
                 }
                 TransactionKind::Dispute
             }
             "resolve" => {
-                if row.amount.is_some() {
-                    return Err(serde::de::Error::custom("resolve must not have amount"));
+                if amount.is_some() {
+                    return Err(format!("resolve {UNEXPECTED_AMOUNT}"));
                 }
                 TransactionKind::Resolve
             }
             "chargeback" => {
-                if row.amount.is_some() {
-                    return Err(serde::de::Error::custom("chargeback must not have amount"));
+                if amount.is_some() {
+                    return Err(format!("chargeback {UNEXPECTED_AMOUNT}"));
                 }
                 TransactionKind::Chargeback
             }
+            "authorize" => {
+                let amount = amount.ok_or_else(|| format!("authorize {MISSING_AMOUNT}"))?;
+                if amount <= Decimal::ZERO {
+                    return Err(format!("authorize {NON_POSITIVE_AMOUNT}"));
+                }
+                TransactionKind::Authorize { amount }
+            }
+            "capture" => {
+                if let Some(amount) = amount
+                    && amount <= Decimal::ZERO
+                {
+                    return Err(format!("capture {NON_POSITIVE_AMOUNT}"));
+                }
+                TransactionKind::Capture { amount }
+            }
+            "void" => {
+                if amount.is_some() {
+                    return Err(format!("void {UNEXPECTED_AMOUNT}"));
+                }
+                TransactionKind::Void
+            }
+            "refund" => {
+                if let Some(amount) = amount
+                    && amount <= Decimal::ZERO
+                {
+                    return Err(format!("refund {NON_POSITIVE_AMOUNT}"));
+                }
+                TransactionKind::Refund { amount }
+            }
+            "reversal" => {
+                if amount.is_some() {
+                    return Err(format!("reversal {UNEXPECTED_AMOUNT}"));
+                }
+                TransactionKind::Reversal
+            }
             _ => {
-                return Err(serde::de::Error::custom(format!(
-                    "unknown transaction type: {}",
-                    row.kind
-                )));
+                return Err(format!("{UNKNOWN_TYPE}: {kind}"));
             }
         };
 
         Ok(Transaction {
-            cid: row.cid,
-            tid: row.tid,
+            cid,
+            tid,
+            timestamp,
             kind,
         })
     }
 }
 
+// The ways the deserializer turns a row down. Either format hands them back as nothing but
+// text, so these are also what tells them apart again, see error_code
+const MISSING_AMOUNT: &str = "requires amount";
+const NON_POSITIVE_AMOUNT: &str = "amount must be positive";
+const UNEXPECTED_AMOUNT: &str = "must not have amount";
+const UNKNOWN_TYPE: &str = "unknown transaction type";
+
+// A short code for the message of a deserialization error, which stays the same no matter
+// how the message is worded or which transaction type it is about
+pub fn error_code(message: &str) -> &'static str {
+    if message.starts_with(UNKNOWN_TYPE) {
+        "unknown-type"
+    } else if message.ends_with(MISSING_AMOUNT) {
+        "missing-amount"
+    } else if message.ends_with(NON_POSITIVE_AMOUNT) {
+        "non-positive-amount"
+    } else if message.ends_with(UNEXPECTED_AMOUNT) {
+        "unexpected-amount"
+    } else if message.starts_with("missing field") {
+        "missing-field"
+    } else {
+        // Whatever serde or the decimal parser has to say about a field
+        "invalid-value"
+    }
+}
+
+// Shared by every input format. Csv lends its strings out, json only does so as long as
+// there's nothing escaped in them
+#[derive(Deserialize)]
+struct TransactionRow<'a, A> {
+    #[serde(rename = "type", borrow)]
+    kind: Cow<'a, str>,
+
+    #[serde(rename = "client")]
+    cid: ClientId,
+
+    #[serde(rename = "tx")]
+    tid: TransactionId,
+
+    amount: Option<A>,
+
+    timestamp: Option<u64>,
+}
+
+impl<A: Into<Decimal>> TransactionRow<'_, A> {
+    fn into_transaction<E: serde::de::Error>(self) -> Result<Transaction, E> {
+        let amount = self.amount.map(Into::into);
+        Transaction::from_fields(&self.kind, self.cid, self.tid, amount, self.timestamp)
+            .map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Transaction {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        TransactionRow::<Decimal>::deserialize(deserializer)?.into_transaction()
+    }
+}
+
+// A transaction out of a csv record. Csv has every field as a string, but serde would take
+// an amount that looks like a number for a float on its way to a Decimal, losing digits
+#[derive(Debug)]
+pub struct CsvTransaction(pub Transaction);
+
+impl<'de> Deserialize<'de> for CsvTransaction {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        TransactionRow::<ExactAmount>::deserialize(deserializer)?
+            .into_transaction()
+            .map(CsvTransaction)
+    }
+}
+
+// Taken exactly as written, an amount which does not fit a Decimal without rounding is
+// refused rather than cut short
+struct ExactAmount(Decimal);
+
+impl From<ExactAmount> for Decimal {
+    fn from(amount: ExactAmount) -> Self {
+        amount.0
+    }
+}
+
+impl<'de> Deserialize<'de> for ExactAmount {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ExactAmountVisitor;
+
+        impl serde::de::Visitor<'_> for ExactAmountVisitor {
+            type Value = ExactAmount;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a decimal amount that fits without rounding")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                let amount = if value.contains(['e', 'E']) {
+                    Decimal::from_scientific(value)
+                } else {
+                    Decimal::from_str_exact(value)
+                };
+                amount
+                    .map(ExactAmount)
+                    .map_err(|_| E::invalid_value(serde::de::Unexpected::Str(value), &self))
+            }
+        }
+
+        deserializer.deserialize_str(ExactAmountVisitor)
+    }
+}
+
+// What to do with an amount that has more decimal places than the policy allows
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ScalePolicy {
+    #[default]
+    Reject,
+    // Rounds half to even, also known as banker's rounding
+    Round,
+    Truncate,
+}
+
+// Checks the amounts right after parsing, before the engine sees them.
+// Without any scale or max amount set, every amount passes as is
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmountPolicy {
+    pub scale: Option<u32>,
+    #[serde(rename = "rounding")]
+    pub scale_policy: ScalePolicy,
+    pub max_amount: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AmountError {
+    TooPrecise {
+        amount: Decimal,
+        scale: u32,
+    },
+    // Rounding or truncating left nothing of the amount
+    VanishesAtScale {
+        amount: Decimal,
+        scale: u32,
+    },
+    TooLarge {
+        amount: Decimal,
+        max_amount: Decimal,
+    },
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AmountError::TooPrecise { amount, scale } => {
+                write!(f, "amount {amount} has more than {scale} decimal places")
+            }
+            AmountError::VanishesAtScale { amount, scale } => {
+                write!(f, "amount {amount} is zero at {scale} decimal places")
+            }
+            AmountError::TooLarge { amount, max_amount } => {
+                write!(f, "amount {amount} exceeds the maximum of {max_amount}")
+            }
+        }
+    }
+}
+
+impl AmountPolicy {
+    pub fn apply(&self, amount: Decimal) -> Result<Decimal, AmountError> {
+        let mut amount = amount;
+        if let Some(scale) = self.scale {
+            // Trailing zeros do not count, 1.50000 is as precise as 1.5
+            let rounded = match self.scale_policy {
+                ScalePolicy::Reject | ScalePolicy::Round => {
+                    amount.round_dp_with_strategy(scale, RoundingStrategy::MidpointNearestEven)
+                }
+                ScalePolicy::Truncate => {
+                    amount.round_dp_with_strategy(scale, RoundingStrategy::ToZero)
+                }
+            };
+            if rounded != amount {
+                if self.scale_policy == ScalePolicy::Reject {
+                    return Err(AmountError::TooPrecise { amount, scale });
+                }
+                if rounded <= Decimal::ZERO {
+                    return Err(AmountError::VanishesAtScale { amount, scale });
+                }
+            }
+            amount = rounded;
+        }
+        if let Some(max_amount) = self.max_amount
+            && amount > max_amount
+        {
+            return Err(AmountError::TooLarge { amount, max_amount });
+        }
+        Ok(amount)
+    }
+}
+
+impl Transaction {
+    pub fn amount(&self) -> Option<Decimal> {
+        match self.kind {
+            TransactionKind::Deposit { amount }
+            | TransactionKind::Withdrawal { amount }
+            | TransactionKind::Authorize { amount } => Some(amount),
+            TransactionKind::Capture { amount } | TransactionKind::Refund { amount } => amount,
+            _ => None,
+        }
+    }
+
+    // Runs the amount of the transaction, if it has any, through the policy
+    pub fn apply_amount_policy(&mut self, policy: &AmountPolicy) -> Result<(), AmountError> {
+        let amount = match &mut self.kind {
+            TransactionKind::Deposit { amount }
+            | TransactionKind::Withdrawal { amount }
+            | TransactionKind::Authorize { amount } => amount,
+            TransactionKind::Capture {
+                amount: Some(amount),
+            }
+            | TransactionKind::Refund {
+                amount: Some(amount),
+            } => amount,
+            _ => return Ok(()),
+        };
+        *amount = policy.apply(*amount)?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::id::tid;
     use csv::ReaderBuilder;
     use rust_decimal::Decimal;
     use std::str::FromStr;
@@ -150,7 +491,7 @@ mod tests {
         .unwrap();
 
         assert_eq!(tx.cid, 1);
-        assert_eq!(tx.tid, 100);
+        assert_eq!(tx.tid, tid(100));
 
         match tx.kind {
             TransactionKind::Deposit { amount } => {
@@ -209,6 +550,105 @@ mod tests {
         matches!(tx.kind, TransactionKind::Chargeback);
     }
 
+    #[test]
+    fn parse_authorize() {
+        let tx = parse_single(
+            "type,client,tx,amount\n\
+             authorize,6,600,12.5",
+        )
+        .unwrap();
+
+        match tx.kind {
+            TransactionKind::Authorize { amount } => {
+                assert_eq!(amount, dec("12.5"));
+            }
+            _ => panic!("expected authorize"),
+        }
+    }
+
+    #[test]
+    fn parse_capture() {
+        let tx = parse_single(
+            "type,client,tx,amount\n\
+             capture,6,600,",
+        )
+        .unwrap();
+
+        match tx.kind {
+            TransactionKind::Capture { amount } => assert_eq!(amount, None),
+            _ => panic!("expected capture"),
+        }
+
+        let tx = parse_single(
+            "type,client,tx,amount\n\
+             capture,6,600,2.5",
+        )
+        .unwrap();
+
+        match tx.kind {
+            TransactionKind::Capture { amount } => assert_eq!(amount, Some(dec("2.5"))),
+            _ => panic!("expected capture"),
+        }
+    }
+
+    #[test]
+    fn parse_void() {
+        let tx = parse_single(
+            "type,client,tx\n\
+             void,6,600",
+        )
+        .unwrap();
+
+        assert!(matches!(tx.kind, TransactionKind::Void));
+    }
+
+    #[test]
+    fn parse_refund() {
+        let tx = parse_single(
+            "type,client,tx,amount\n\
+             refund,7,700,1.5",
+        )
+        .unwrap();
+
+        match tx.kind {
+            TransactionKind::Refund { amount } => assert_eq!(amount, Some(dec("1.5"))),
+            _ => panic!("expected refund"),
+        }
+    }
+
+    #[test]
+    fn parse_reversal() {
+        let tx = parse_single(
+            "type,client,tx\n\
+             reversal,7,700",
+        )
+        .unwrap();
+
+        assert!(matches!(tx.kind, TransactionKind::Reversal));
+    }
+
+    #[test]
+    fn parse_timestamp() {
+        let tx = parse_single(
+            "type,client,tx,amount,timestamp\n\
+             withdrawal,1,1,1.0,1700000000",
+        )
+        .unwrap();
+
+        assert_eq!(tx.timestamp, Some(1700000000));
+    }
+
+    #[test]
+    fn timestamp_is_optional() {
+        let tx = parse_single(
+            "type,client,tx,amount\n\
+             withdrawal,1,1,1.0",
+        )
+        .unwrap();
+
+        assert_eq!(tx.timestamp, None);
+    }
+
     // -------------------------
     // Validation errors
     // -------------------------
@@ -293,6 +733,61 @@ mod tests {
         assert!(err.to_string().contains("chargeback must not have amount"));
     }
 
+    #[test]
+    fn authorize_requires_amount() {
+        let err = parse_single(
+            "type,client,tx\n\
+             authorize,1,1",
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("authorize requires amount"));
+    }
+
+    #[test]
+    fn capture_amount_must_be_positive() {
+        let err = parse_single(
+            "type,client,tx,amount\n\
+             capture,1,1,0",
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("capture amount must be positive"));
+    }
+
+    #[test]
+    fn void_must_not_have_amount() {
+        let err = parse_single(
+            "type,client,tx,amount\n\
+             void,1,1,1.0",
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("void must not have amount"));
+    }
+
+    #[test]
+    fn refund_amount_must_be_positive() {
+        let err = parse_single(
+            "type,client,tx,amount\n\
+             refund,1,1,-1.0",
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("refund amount must be positive"));
+    }
+
+    #[test]
+    fn reversal_must_not_have_amount() {
+        let err = parse_single(
+            "type,client,tx,amount\n\
+             reversal,1,1,1.0",
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("reversal must not have amount"));
+    }
+
     #[test]
     fn unknown_transaction_type() {
         let err = parse_single(
@@ -323,4 +818,100 @@ mod tests {
             _ => panic!("expected deposit"),
         }
     }
+
+    // -------------------------
+    // Amount policy
+    // -------------------------
+
+    #[test]
+    fn policy_rejects_excess_precision() {
+        let policy = AmountPolicy {
+            scale: Some(4),
+            ..Default::default()
+        };
+
+        assert_eq!(policy.apply(dec("1.50000")), Ok(dec("1.50000")));
+        assert_eq!(
+            policy.apply(dec("1.00005")),
+            Err(AmountError::TooPrecise {
+                amount: dec("1.00005"),
+                scale: 4
+            })
+        );
+        assert_eq!(
+            AmountError::TooPrecise {
+                amount: dec("1.00005"),
+                scale: 4
+            }
+            .to_string(),
+            "amount 1.00005 has more than 4 decimal places"
+        );
+    }
+
+    #[test]
+    fn policy_rounds_half_to_even() {
+        let policy = AmountPolicy {
+            scale: Some(4),
+            scale_policy: ScalePolicy::Round,
+            ..Default::default()
+        };
+
+        assert_eq!(policy.apply(dec("1.00005")), Ok(dec("1.0000")));
+        assert_eq!(policy.apply(dec("1.00015")), Ok(dec("1.0002")));
+        assert_eq!(
+            policy.apply(dec("0.00004")),
+            Err(AmountError::VanishesAtScale {
+                amount: dec("0.00004"),
+                scale: 4
+            })
+        );
+    }
+
+    #[test]
+    fn policy_truncates() {
+        let policy = AmountPolicy {
+            scale: Some(2),
+            scale_policy: ScalePolicy::Truncate,
+            max_amount: Some(dec("100")),
+        };
+
+        assert_eq!(policy.apply(dec("1.999")), Ok(dec("1.99")));
+        // The maximum applies to what is left after truncation
+        assert_eq!(policy.apply(dec("100.009")), Ok(dec("100.00")));
+        assert_eq!(
+            policy.apply(dec("100.01")),
+            Err(AmountError::TooLarge {
+                amount: dec("100.01"),
+                max_amount: dec("100")
+            })
+        );
+    }
+
+    #[test]
+    fn policy_applies_to_optional_amounts_only_when_present() {
+        let policy = AmountPolicy {
+            scale: Some(1),
+            scale_policy: ScalePolicy::Round,
+            ..Default::default()
+        };
+
+        let mut tx = parse_single(
+            "type,client,tx,amount\n\
+             refund,1,1,1.25",
+        )
+        .unwrap();
+        tx.apply_amount_policy(&policy).unwrap();
+        match tx.kind {
+            TransactionKind::Refund { amount } => assert_eq!(amount, Some(dec("1.2"))),
+            _ => panic!("expected refund"),
+        }
+
+        let mut tx = parse_single(
+            "type,client,tx,amount\n\
+             capture,1,1,",
+        )
+        .unwrap();
+        tx.apply_amount_policy(&policy).unwrap();
+        assert!(matches!(tx.kind, TransactionKind::Capture { amount: None }));
+    }
 }
@@ -0,0 +1,174 @@
+use std::{collections::HashSet, fmt};
+
+use serde::Serialize;
+
+use crate::{ClientId, Transaction, TransactionId, output::Columns, rejection::Rejection};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IssueKind {
+    // The row could not be parsed into a transaction at all
+    Malformed,
+    // A deposit, withdrawal or authorization reusing the tid of an earlier one
+    DuplicateTid,
+    UnknownTid,
+    ForeignTid,
+    // A resolve or chargeback of a transaction which is not under dispute
+    NotDisputed,
+    Overdraft,
+    LockedAccount,
+    // Turned down by the amount policy
+    InvalidAmount,
+    // Any other reason for the engine to turn the transaction down
+    Rejected,
+}
+
+impl From<Rejection> for IssueKind {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::UnknownTransaction => IssueKind::UnknownTid,
+            Rejection::ForeignTransaction => IssueKind::ForeignTid,
+            Rejection::NotDisputed => IssueKind::NotDisputed,
+            Rejection::InsufficientFunds => IssueKind::Overdraft,
+            Rejection::AccountLocked => IssueKind::LockedAccount,
+            _ => IssueKind::Rejected,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Issue {
+    // The line of the input the record starts on
+    pub line: u64,
+    pub client: Option<ClientId>,
+    pub tx: Option<TransactionId>,
+    pub kind: IssueKind,
+    pub message: String,
+}
+
+impl Columns for Issue {
+    const COLUMNS: &'static [&'static str] = &["line", "client", "tx", "kind", "message"];
+}
+
+// Collects everything wrong with an input while it is fed through the engine as usual,
+// so what gets reported is exactly what a real run would turn down
+#[derive(Debug, Default)]
+pub struct Validator {
+    // How many records were checked, malformed ones included
+    pub rows: u64,
+    pub issues: Vec<Issue>,
+    // The engine lets a later transaction take over the tid, so it has to be caught here
+    tids: HashSet<TransactionId>,
+}
+
+impl Validator {
+    // Has to go before the transaction is processed
+    pub fn check(&mut self, line: u64, transaction: &Transaction) {
+        self.rows += 1;
+        if transaction.kind.creates_tid() && !self.tids.insert(transaction.tid) {
+            self.issues.push(Issue {
+                line,
+                client: Some(transaction.cid),
+                tx: Some(transaction.tid),
+                kind: IssueKind::DuplicateTid,
+                message: format!("transaction {} was seen before", transaction.tid),
+            });
+        }
+    }
+
+    pub fn record_rejection(
+        &mut self,
+        line: u64,
+        transaction: &Transaction,
+        kind: IssueKind,
+        reason: &dyn fmt::Display,
+    ) {
+        self.issues.push(Issue {
+            line,
+            client: Some(transaction.cid),
+            tx: Some(transaction.tid),
+            kind,
+            message: format!("{}: {reason}", transaction.kind.name()),
+        });
+    }
+
+    pub fn record_malformed(&mut self, line: u64, error: &dyn fmt::Display) {
+        self.rows += 1;
+        self.issues.push(Issue {
+            line,
+            client: None,
+            tx: None,
+            kind: IssueKind::Malformed,
+            message: error.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{input, payments::Payments};
+
+    #[test]
+    fn issues_are_classified() {
+        let mut payments = Payments::default();
+        let mut validator = Validator::default();
+        let transactions = input::read_csv(
+            "type,client,tx,amount\n\
+             deposit,1,1,5\n\
+             deposit,1,1,5\n\
+             withdrawal,1,2,20\n\
+             \n\
+             dispute,2,1,\n\
+             dispute,1,9,\n\
+             resolve,1,1,\n\
+             bogus,1,3,\n\
+             dispute,1,1,\n\
+             chargeback,1,1,\n\
+             deposit,1,4,1\n"
+                .as_bytes(),
+            &input::CsvDialect::default(),
+        )
+        .unwrap();
+        for (line, result) in transactions {
+            match result {
+                Ok(transaction) => {
+                    validator.check(line, &transaction);
+                    if let Err(rejection) = payments.process_transaction(&transaction) {
+                        validator.record_rejection(
+                            line,
+                            &transaction,
+                            rejection.into(),
+                            &rejection,
+                        );
+                    }
+                }
+                Err(error) => validator.record_malformed(line, &error),
+            }
+        }
+
+        let issues: Vec<_> = validator
+            .issues
+            .iter()
+            .map(|issue| (issue.line, issue.kind))
+            .collect();
+        // Lines of the input, the header and the blank line count as well
+        assert_eq!(
+            issues,
+            [
+                (3, IssueKind::DuplicateTid),
+                (4, IssueKind::Overdraft),
+                (6, IssueKind::ForeignTid),
+                (7, IssueKind::UnknownTid),
+                (8, IssueKind::NotDisputed),
+                (9, IssueKind::Malformed),
+                (12, IssueKind::LockedAccount),
+            ]
+        );
+        assert_eq!(validator.rows, 10);
+        assert_eq!(
+            validator.issues[2].message,
+            "dispute: referenced transaction belongs to another client"
+        );
+    }
+}